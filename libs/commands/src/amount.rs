@@ -75,19 +75,32 @@ impl<T: AmountValue> FromStr for Amount<T> {
 
 impl<T: AmountValue> Amount<T> {
     pub fn get_value(&self) -> T {
-        match self {
-            Self::Range { start, finish } => {
-                let range = (*start)..(*finish);
-
-                let mut rng = rand::thread_rng();
+        self.get_value_with(&mut rand::thread_rng())
+    }
 
-                rng.gen_range(range)
-            }
+    /// Picks a value using the given random number generator, so the same values can be picked again
+    pub fn get_value_with(&self, rng: &mut impl Rng) -> T {
+        match self {
+            Self::Range { start, finish } => rng.gen_range((*start)..(*finish)),
             Self::Single(number) => *number,
         }
     }
 }
 
+impl Amount<u64> {
+    /// Picks a value in milliseconds, scaled by the current [playback speed](crate::speed::speed)
+    #[must_use]
+    pub fn get_delay(&self) -> std::time::Duration {
+        self.get_delay_with(&mut rand::thread_rng())
+    }
+
+    /// Like [`Amount::get_delay`], picking the value using the given random number generator
+    #[must_use]
+    pub fn get_delay_with(&self, rng: &mut impl Rng) -> std::time::Duration {
+        crate::speed::scale(std::time::Duration::from_millis(self.get_value_with(rng)))
+    }
+}
+
 impl<T: AmountValue> std::fmt::Display for Amount<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let value = self.get_value();
@@ -0,0 +1,150 @@
+//! Conversion of the command intermediate representation (`.cmdir`) recorded while running, into a regular `.commands` script
+//!
+//! A `.cmdir` file is made up of regular commands, each preceded by an `end_pause(<unix ms>)` marker noting when it was run.
+//! The gaps between markers become `sleep` commands, minus any time already spent sleeping.
+
+use thiserror::Error;
+
+use crate::{grammar::ParseError, Command, CommandsError};
+
+#[derive(Debug, Error)]
+pub enum CmdirError {
+    #[error("Invalid end_pause marker on line {line}: {source}")]
+    InvalidPause {
+        line: usize,
+        source: std::num::ParseIntError,
+    },
+    #[error("Invalid command on line {line}: {source}")]
+    InvalidCommand {
+        line: usize,
+        source: Box<CommandsError>,
+    },
+}
+
+/// Parses the timestamp out of an `end_pause(<unix ms>)` marker, or [`None`] if the line is not a marker
+fn parse_pause(line: &str) -> Option<Result<u128, std::num::ParseIntError>> {
+    let inner = line.strip_prefix("end_pause(")?.strip_suffix(')')?;
+
+    Some(inner.trim().parse())
+}
+
+/// Converts the contents of a `.cmdir` file into the equivalent commands
+pub fn parse(input: &str) -> Result<Vec<Command>, CmdirError> {
+    let mut commands = Vec::new();
+
+    let mut last_pause: Option<u128> = None;
+    // Time already accounted for by sleeps since the last marker
+    let mut slept: u128 = 0;
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(timestamp) = parse_pause(line) {
+            let timestamp = timestamp.map_err(|source| CmdirError::InvalidPause {
+                line: i + 1,
+                source,
+            })?;
+
+            if let Some(last_pause) = last_pause {
+                let gap = timestamp.saturating_sub(last_pause).saturating_sub(slept);
+
+                push_sleep(&mut commands, u64::try_from(gap).unwrap_or(u64::MAX));
+            }
+
+            last_pause = Some(timestamp);
+            slept = 0;
+
+            continue;
+        }
+
+        let command = match Command::try_from(line.to_string()) {
+            Ok(command) => command,
+            Err(CommandsError::GrammarError(ParseError::Comment)) => continue,
+            Err(e) => {
+                return Err(CmdirError::InvalidCommand {
+                    line: i + 1,
+                    source: Box::new(e),
+                })
+            }
+        };
+
+        match command {
+            Command::Sleep { delay } => {
+                slept += u128::from(delay);
+                push_sleep(&mut commands, delay);
+            }
+            command @ Command::Send { .. } => commands.push(command),
+        }
+    }
+
+    // Anything after the final message has no effect
+    while matches!(commands.last(), Some(Command::Sleep { .. })) {
+        commands.pop();
+    }
+
+    Ok(commands)
+}
+
+/// Adds a sleep, merging it into the previous command if that was also a sleep
+fn push_sleep(commands: &mut Vec<Command>, delay: u64) {
+    if delay == 0 {
+        return;
+    }
+
+    if let Some(Command::Sleep { delay: previous }) = commands.last_mut() {
+        *previous = previous.saturating_add(delay);
+    } else {
+        commands.push(Command::Sleep { delay });
+    }
+}
+
+/// Converts the contents of a `.cmdir` file into a `.commands` script
+pub fn to_script(input: &str) -> Result<String, CmdirError> {
+    let mut script = String::new();
+
+    for command in parse(input)? {
+        script.push_str(&command.to_string());
+        script.push('\n');
+    }
+
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_recording() {
+        let script = to_script(include_str!("../fixtures/legacy.cmdir")).unwrap();
+
+        assert_eq!(script, include_str!("../fixtures/legacy.commands"));
+    }
+
+    #[test]
+    fn test_session_recording() {
+        let script = to_script(include_str!("../fixtures/session.cmdir")).unwrap();
+
+        assert_eq!(script, include_str!("../fixtures/session.commands"));
+    }
+
+    #[test]
+    fn test_converted_script_parses() {
+        let script = to_script(include_str!("../fixtures/session.cmdir")).unwrap();
+
+        for line in script.lines() {
+            Command::try_from(line.to_string()).expect("valid command");
+        }
+    }
+
+    #[test]
+    fn test_invalid_pause() {
+        let error = parse("end_pause(123)\nend_pause(abc)").unwrap_err();
+
+        assert!(matches!(error, CmdirError::InvalidPause { line: 2, .. }));
+    }
+}
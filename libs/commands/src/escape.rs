@@ -0,0 +1,140 @@
+//! Escaping of strings in commands, following the escapes `grammar.pest` accepts
+//!
+//! These are the escapes of JSON, where characters outside the BMP are written as a `\uXXXX` surrogate pair.
+
+use std::fmt::Write;
+
+/// Escapes the string so that it can be written between quotes in a command
+///
+/// Quotes, backslashes and line breaks are always escaped, as are characters that would otherwise be invisible,
+/// such as control characters, combining marks and zero width joiners.
+#[must_use]
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // Anything Rust would not print as is
+            c if c.escape_debug().nth(1).is_some() => {
+                let mut units = [0; 2];
+
+                for unit in c.encode_utf16(&mut units) {
+                    write!(escaped, "\\u{unit:04x}").expect("writing to a String");
+                }
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Reads the four hex digits of a `\u` escape
+fn hex_unit(chars: &mut std::str::Chars) -> Option<u16> {
+    let digits: String = chars.take(4).collect();
+
+    u16::from_str_radix(&digits, 16).ok()
+}
+
+/// Reverses [`escape`], where `quoted` is a string as matched by the grammar, quotes included
+///
+/// Escapes the grammar doesn't allow are kept as they are, and unpaired surrogates become U+FFFD.
+#[must_use]
+pub fn unescape(quoted: &str) -> String {
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(quoted);
+
+    let mut unescaped = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('b') => unescaped.push('\u{8}'),
+            Some('f') => unescaped.push('\u{c}'),
+            Some('u') => {
+                let Some(unit) = hex_unit(&mut chars) else {
+                    unescaped.push(char::REPLACEMENT_CHARACTER);
+                    continue;
+                };
+
+                // A high surrogate is followed by the `\uXXXX` of its low surrogate
+                let mut units = vec![unit];
+
+                if (0xD800..0xDC00).contains(&unit) && chars.as_str().starts_with("\\u") {
+                    let mut lookahead = chars.clone();
+                    lookahead.nth(1);
+
+                    if let Some(low) =
+                        hex_unit(&mut lookahead).filter(|low| (0xDC00..0xE000).contains(low))
+                    {
+                        units.push(low);
+                        chars = lookahead;
+                    }
+                }
+
+                unescaped.extend(
+                    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+                );
+            }
+            // `\"`, `\\` and `\/`
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("Say \"hi\"\n"), "Say \\\"hi\\\"\\n");
+        assert_eq!(escape("a\\b"), "a\\\\b");
+        // Readable characters are left alone, invisible ones are not
+        assert_eq!(escape("héllo 🦀"), "héllo 🦀");
+        assert_eq!(escape("e\u{301}"), "e\\u0301");
+        assert_eq!(escape("\u{200b}"), "\\u200b");
+        // Outside the BMP, as a surrogate pair
+        assert_eq!(escape("\u{e0041}"), "\\udb40\\udc41");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for s in [
+            "plain",
+            "Say \"hi\" \\o/\r\n\ttabbed",
+            "e\u{301}",
+            "👩\u{200d}💻",
+            "🏴\u{e0067}\u{e0062}\u{e0073}\u{e0063}\u{e0074}\u{e007f}",
+            "zero\u{200b}width",
+            "\u{0}\u{7f}",
+        ] {
+            assert_eq!(unescape(&format!("\"{}\"", escape(s))), s);
+        }
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("\"a\\/b\""), "a/b");
+        assert_eq!(unescape("\"\\u00e9\\ud83e\\udd80\""), "é🦀");
+        assert_eq!(unescape("\"\\ud83e\""), "\u{fffd}");
+    }
+}
@@ -26,7 +26,7 @@ impl CommandInfo {
         match cmd_name.to_lowercase().as_str() {
             "send" => Ok(CommandInfo {
                 name: "send",
-                arg_count: 4,
+                arg_count: 5,
             }),
             "sleep" => Ok(CommandInfo {
                 name: "sleep",
@@ -102,6 +102,7 @@ mod tests {
             username: String::from("random"),
             count: 10,
             delay: amount::Amount::Single(10),
+            channel: None,
         };
 
         assert_eq!(command, act);
@@ -14,6 +14,12 @@ pub mod grammar;
 
 pub mod amount;
 
+pub mod cmdir;
+
+pub mod escape;
+
+pub mod speed;
+
 #[derive(Debug, Error)]
 pub enum CommandsError {
     #[error("The number provided was invalid")]
@@ -45,6 +51,8 @@ pub enum Command {
         username: String,
         count: usize,
         delay: Amount<u64>,
+        /// The channel to send to, or the configured default if [`None`]
+        channel: Option<String>,
     },
     /// Pauses for the given number of milliseconds
     Sleep { delay: u64 },
@@ -53,10 +61,7 @@ pub enum Command {
 impl amount::AmountValue for u64 {}
 
 fn parse_str_lit(lit: &str) -> String {
-    let parsed = litrs::StringLit::parse(lit).expect("valid string literal");
-    let value = parsed.value();
-
-    value.to_string()
+    escape::unescape(lit)
 }
 
 impl Command {
@@ -74,19 +79,19 @@ impl Command {
                 username: parse_str_lit(parts.get(4).copied().unwrap_or("\"random\"")),
                 count: parts[2].parse()?,
                 delay: dbg!(parts[3]).parse()?,
+                channel: parts.get(5).copied().map(parse_str_lit),
             }),
             _ => unreachable!("Any invalid command error should have been caught above"),
         }
     }
 
+    /// The delay following this command, scaled by the current [playback speed](speed::speed)
     #[must_use]
     pub fn get_delay(&self) -> Duration {
-        let delay_ms = match self {
-            Command::Send { delay, .. } => delay.get_value(),
-            Command::Sleep { delay } => *delay,
-        };
-
-        Duration::from_millis(delay_ms)
+        match self {
+            Command::Send { delay, .. } => delay.get_delay(),
+            Command::Sleep { delay } => speed::scale(Duration::from_millis(*delay)),
+        }
     }
 }
 
@@ -108,12 +113,18 @@ impl std::fmt::Display for Command {
                 username,
                 count,
                 delay,
+                channel,
             } => {
-                write!(f, "send(\"{message}\", {count}, {delay}")?;
+                // Strings are written escaped, so that they can be parsed back
+                write!(f, "send(\"{}\", {count}, {delay}", escape::escape(message))?;
 
-                // Only embed the username if it is not "random"
-                if username != "random" {
-                    write!(f, ", \"{username}\"")?;
+                // Only embed the username if it is not "random", or if it is needed to position the channel
+                if username != "random" || channel.is_some() {
+                    write!(f, ", \"{}\"", escape::escape(username))?;
+                }
+
+                if let Some(channel) = channel {
+                    write!(f, ", \"{}\"", escape::escape(channel))?;
                 }
 
                 write!(f, ")")
@@ -145,6 +156,7 @@ mod tests {
             username: String::from("random"),
             count: 3,
             delay: Amount::Single(1000),
+            channel: None,
         };
 
         assert_eq!(cmd.to_string(), dest);
@@ -155,8 +167,54 @@ mod tests {
             username: String::from("justinfan"),
             count: 15,
             delay: Amount::Single(10),
+            channel: None,
         };
 
         assert_eq!(cmd.to_string(), dest);
+
+        let dest = "send(\"Hello, World!\", 1, 10, \"random\", \"#somechannel\")";
+        let cmd = Command::Send {
+            message: String::from("Hello, World!"),
+            username: String::from("random"),
+            count: 1,
+            delay: Amount::Single(10),
+            channel: Some(String::from("#somechannel")),
+        };
+
+        assert_eq!(cmd.to_string(), dest);
+        assert_eq!(Command::try_from(dest.to_string()).unwrap(), cmd);
+
+        let dest = "send(\"Say \\\"hi\\\"\", 1, 10)";
+        let cmd = Command::Send {
+            message: String::from("Say \"hi\""),
+            username: String::from("random"),
+            count: 1,
+            delay: Amount::Single(10),
+            channel: None,
+        };
+
+        assert_eq!(cmd.to_string(), dest);
+        assert_eq!(Command::try_from(dest.to_string()).unwrap(), cmd);
+    }
+
+    #[test]
+    fn test_non_ascii_round_trip() {
+        for message in [
+            "héllo 🦀",
+            "e\u{301}",
+            "👩\u{200d}💻",
+            "tag\u{e0041}",
+            "a\u{200b}b\nc",
+        ] {
+            let cmd = Command::Send {
+                message: String::from(message),
+                username: String::from("random"),
+                count: 1,
+                delay: Amount::Single(10),
+                channel: None,
+            };
+
+            assert_eq!(Command::try_from(cmd.to_string()).unwrap(), cmd);
+        }
     }
 }
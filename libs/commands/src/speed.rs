@@ -0,0 +1,77 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 100.0;
+
+/// The bits of the current speed multiplier, as atomics cannot hold an [`f64`] directly
+///
+/// Starts at `1.0_f64.to_bits()`
+static SPEED: AtomicU64 = AtomicU64::new(0x3FF0_0000_0000_0000);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SpeedError {
+    #[error("Speed must be between {MIN_SPEED}x and {MAX_SPEED}x. Was given {0}x")]
+    OutOfRange(f64),
+}
+
+/// The multiplier applied to every delay, where `2.0` plays back twice as fast
+#[must_use]
+pub fn speed() -> f64 {
+    f64::from_bits(SPEED.load(Ordering::Relaxed))
+}
+
+/// Sets the playback speed, returning the previous one
+pub fn set_speed(speed: f64) -> Result<f64, SpeedError> {
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(SpeedError::OutOfRange(speed));
+    }
+
+    Ok(f64::from_bits(
+        SPEED.swap(speed.to_bits(), Ordering::Relaxed),
+    ))
+}
+
+/// Scales a delay by the current playback speed
+#[must_use]
+pub fn scale(delay: Duration) -> Duration {
+    delay.div_f64(speed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The speeds used are exactly representable, so comparing them is fine
+    #[allow(clippy::float_cmp)]
+    #[test]
+    fn test_set_speed() {
+        assert_eq!(speed(), 1.0);
+        assert_eq!(
+            scale(Duration::from_millis(1000)),
+            Duration::from_millis(1000)
+        );
+
+        assert_eq!(set_speed(10.0), Ok(1.0));
+        assert_eq!(
+            scale(Duration::from_millis(1000)),
+            Duration::from_millis(100)
+        );
+
+        assert_eq!(set_speed(0.5), Ok(10.0));
+        assert_eq!(
+            scale(Duration::from_millis(1000)),
+            Duration::from_millis(2000)
+        );
+
+        assert_eq!(set_speed(0.0), Err(SpeedError::OutOfRange(0.0)));
+        assert_eq!(set_speed(101.0), Err(SpeedError::OutOfRange(101.0)));
+        assert!(set_speed(f64::NAN).is_err());
+
+        assert_eq!(set_speed(1.0), Ok(0.5));
+    }
+}
@@ -0,0 +1,545 @@
+//! Logging in with Twitch, to get the tokens used by the rest of the API
+//!
+//! Supports both the [device code grant](https://dev.twitch.tv/docs/authentication/getting-tokens-oauth/#device-code-grant-flow),
+//! where the user enters a code on another device, and the
+//! [authorization code grant](https://dev.twitch.tv/docs/authentication/getting-tokens-oauth/#authorization-code-grant-flow),
+//! where the browser redirects back to a local server.
+
+use std::time::Duration;
+
+use reqwest::Url;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+use crate::creds::Credentials;
+
+pub const TWITCH_ID_URL: &str = "https://id.twitch.tv";
+
+/// The scopes needed to read the roles of everyone in the pool
+pub const SCOPES: [&str; 5] = [
+    "moderator:read:followers",
+    "moderator:read:chatters",
+    "moderation:read",
+    "channel:read:vips",
+    "channel:read:subscriptions",
+];
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Failed to reach Twitch: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Failed to receive the redirect: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Twitch returned an error ({status}): {message}")]
+    Twitch { status: u16, message: String },
+    #[error("The login was denied")]
+    Denied,
+    #[error("The login code expired before it was used")]
+    Expired,
+    #[error("The redirect did not come from this login attempt")]
+    StateMismatch,
+    #[error("The redirect did not include an authorization code")]
+    MissingCode,
+}
+
+/// The error body Twitch responds with
+#[derive(Debug, Deserialize)]
+pub(crate) struct TwitchError {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+}
+
+/// Who a token belongs to, and how long it has left
+#[derive(Debug, Clone, Deserialize)]
+pub struct Validation {
+    pub user_id: String,
+    pub login: String,
+    /// Seconds until the token expires
+    pub expires_in: u64,
+}
+
+/// A pending device code login, waiting for the user to enter the code
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    /// The code the user enters
+    pub user_code: String,
+    /// Where the user enters the code, with the code already filled in
+    pub verification_uri: String,
+    /// Seconds until the code expires
+    pub expires_in: u64,
+    /// Seconds to wait between checking whether the user has logged in
+    pub interval: u64,
+}
+
+/// The application logging in, along with where to reach Twitch
+#[derive(Debug, Clone)]
+pub struct OAuth {
+    base_url: String,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl OAuth {
+    /// Uses `FAUXCHAT_ID_URL` if it is set, otherwise Twitch
+    pub fn new(client_id: impl Into<String>, client_secret: impl Into<String>) -> Self {
+        Self {
+            base_url: std::env::var("FAUXCHAT_ID_URL")
+                .unwrap_or_else(|_| TWITCH_ID_URL.to_string()),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scopes: SCOPES.iter().map(ToString::to_string).collect(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Sends requests somewhere other than `id.twitch.tv`, such as a mock server
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/oauth2/{path}", self.base_url.trim_end_matches('/'))
+    }
+
+    /// Reads the body of a successful response, or the error Twitch gave
+    async fn parse<T: for<'de> Deserialize<'de>>(resp: reqwest::Response) -> Result<T, AuthError> {
+        let status = resp.status();
+
+        if status.is_success() {
+            return Ok(resp.json().await?);
+        }
+
+        let text = resp.text().await?;
+
+        Err(match serde_json::from_str::<TwitchError>(&text) {
+            Ok(error) => AuthError::Twitch {
+                status: error.status,
+                message: error.message,
+            },
+            Err(_) => AuthError::Twitch {
+                status: status.as_u16(),
+                message: text,
+            },
+        })
+    }
+
+    async fn token(&self, form: &[(&str, &str)]) -> Result<TokenResponse, AuthError> {
+        let resp = self
+            .client
+            .post(self.url("token"))
+            .form(form)
+            .send()
+            .await?;
+
+        Self::parse(resp).await
+    }
+
+    /// Checks the token is still valid, which Twitch requires apps to do hourly
+    pub async fn validate(&self, token: &str) -> Result<Validation, AuthError> {
+        let resp = self
+            .client
+            .get(self.url("validate"))
+            .header("Authorization", format!("OAuth {token}"))
+            .send()
+            .await?;
+
+        Self::parse(resp).await
+    }
+
+    /// Gets a new token for the credentials, keeping who they belong to
+    pub async fn refresh(&self, creds: &Credentials) -> Result<Credentials, AuthError> {
+        let tokens = self
+            .token(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", &self.client_secret),
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &creds.refresh_token),
+            ])
+            .await?;
+
+        Ok(Credentials {
+            auth_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            ..creds.clone()
+        })
+    }
+
+    /// Turns the tokens into credentials, looking up who they belong to
+    async fn credentials(&self, tokens: TokenResponse) -> Result<Credentials, AuthError> {
+        let validation = self.validate(&tokens.access_token).await?;
+
+        Ok(Credentials {
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+            user_id: validation.user_id,
+            auth_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+        })
+    }
+
+    /// Starts a device code login, returning the code for the user to enter
+    pub async fn start_device_flow(&self) -> Result<DeviceCode, AuthError> {
+        let scopes = self.scopes.join(" ");
+
+        let resp = self
+            .client
+            .post(self.url("device"))
+            .form(&[("client_id", self.client_id.as_str()), ("scopes", &scopes)])
+            .send()
+            .await?;
+
+        Self::parse(resp).await
+    }
+
+    /// Waits for the user to enter the code, returning the credentials once they have
+    pub async fn finish_device_flow(&self, code: &DeviceCode) -> Result<Credentials, AuthError> {
+        let scopes = self.scopes.join(" ");
+        let mut interval = Duration::from_secs(code.interval);
+        let expires = tokio::time::Instant::now() + Duration::from_secs(code.expires_in);
+
+        loop {
+            if tokio::time::Instant::now() >= expires {
+                return Err(AuthError::Expired);
+            }
+
+            let result = self
+                .token(&[
+                    ("client_id", self.client_id.as_str()),
+                    ("scopes", &scopes),
+                    ("device_code", &code.device_code),
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ])
+                .await;
+
+            match result {
+                Ok(tokens) => return self.credentials(tokens).await,
+                Err(AuthError::Twitch { message, .. }) if message == "authorization_pending" => {}
+                Err(AuthError::Twitch { message, .. }) if message == "slow_down" => {
+                    interval += Duration::from_secs(5);
+                }
+                Err(AuthError::Twitch { message, .. }) if message == "access_denied" => {
+                    return Err(AuthError::Denied);
+                }
+                Err(AuthError::Twitch { message, .. }) if message == "expired_token" => {
+                    return Err(AuthError::Expired);
+                }
+                Err(e) => return Err(e),
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// The page to send the user to, which redirects back with a code once they log in
+    #[must_use]
+    pub fn authorize_url(&self, redirect_uri: &str, state: &str) -> String {
+        let scopes = self.scopes.join(" ");
+
+        Url::parse_with_params(
+            &self.url("authorize"),
+            [
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", redirect_uri),
+                ("scope", &scopes),
+                ("state", state),
+            ],
+        )
+        .map(String::from)
+        .unwrap_or_default()
+    }
+
+    /// Exchanges the code from the redirect for credentials
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+    ) -> Result<Credentials, AuthError> {
+        let tokens = self
+            .token(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", &self.client_secret),
+                ("code", code),
+                ("grant_type", "authorization_code"),
+                ("redirect_uri", redirect_uri),
+            ])
+            .await?;
+
+        self.credentials(tokens).await
+    }
+
+    /// Logs in through the browser, with Twitch redirecting back to the given listener
+    ///
+    /// The redirect URI must be registered for the application, i.e. `http://localhost:17563`.
+    /// `open` is given the page the user needs to visit.
+    pub async fn authorization_code_flow(
+        &self,
+        listener: TcpListener,
+        redirect_uri: &str,
+        open: impl FnOnce(&str),
+    ) -> Result<Credentials, AuthError> {
+        let state = random_state();
+
+        open(&self.authorize_url(redirect_uri, &state));
+
+        let code = receive_code(&listener, &state).await?;
+
+        self.exchange_code(&code, redirect_uri).await
+    }
+}
+
+/// A random value tying the redirect to this login attempt
+fn random_state() -> String {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Waits for the browser to be redirected back, returning the authorization code
+async fn receive_code(listener: &TcpListener, state: &str) -> Result<String, AuthError> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let mut stream = BufReader::new(stream);
+
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).await?;
+
+        // Only the request line matters, i.e. `GET /?code=...&state=... HTTP/1.1`
+        let Some(target) = request_line.split_whitespace().nth(1) else {
+            continue;
+        };
+
+        let Ok(url) = Url::parse(&format!("http://localhost{target}")) else {
+            continue;
+        };
+
+        let query = |key: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.to_string())
+        };
+
+        // Browsers also ask for things such as the favicon, which can be ignored
+        if query("state").is_none() && query("error").is_none() {
+            respond(stream.get_mut(), "404 Not Found", "").await?;
+            continue;
+        }
+
+        let result = if query("state").as_deref() != Some(state) {
+            Err(AuthError::StateMismatch)
+        } else if query("error").is_some() {
+            Err(AuthError::Denied)
+        } else {
+            query("code").ok_or(AuthError::MissingCode)
+        };
+
+        let page = if result.is_ok() {
+            "Logged in to FauxChat, you can close this window."
+        } else {
+            "Failed to log in to FauxChat, please try again."
+        };
+
+        respond(stream.get_mut(), "200 OK", page).await?;
+
+        return result;
+    }
+}
+
+async fn respond(
+    stream: &mut tokio::net::TcpStream,
+    status: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::mock::{self, Reply};
+
+    fn tokens() -> Reply {
+        (
+            200,
+            r#"{"access_token":"access","refresh_token":"refresh","expires_in":14400,"scope":[],"token_type":"bearer"}"#.to_string(),
+        )
+    }
+
+    fn validation() -> Reply {
+        (
+            200,
+            r#"{"client_id":"id","login":"fauxchat","scopes":[],"user_id":"4321","expires_in":14400}"#.to_string(),
+        )
+    }
+
+    fn twitch_error(message: &str) -> Reply {
+        mock::twitch_error(400, message)
+    }
+
+    #[tokio::test]
+    async fn test_device_flow() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&polls);
+
+        let base_url = mock::serve(move |req| match req.path.as_str() {
+            "/oauth2/device" => {
+                assert!(req.body.contains("client_id=id"));
+
+                (
+                    200,
+                    r#"{"device_code":"device","expires_in":1800,"interval":0,"user_code":"ABCDEFGH","verification_uri":"https://www.twitch.tv/activate?device-code=ABCDEFGH"}"#.to_string(),
+                )
+            }
+            "/oauth2/token" => {
+                assert!(req.body.contains("device_code=device"));
+
+                // The user takes a couple of checks to enter the code
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    twitch_error("authorization_pending")
+                } else {
+                    tokens()
+                }
+            }
+            "/oauth2/validate" => validation(),
+            _ => (404, String::new()),
+        })
+        .await
+        .unwrap();
+
+        let oauth = OAuth::new("id", "secret").with_base_url(base_url);
+
+        let code = oauth.start_device_flow().await.unwrap();
+        assert_eq!(code.user_code, "ABCDEFGH");
+
+        let creds = oauth.finish_device_flow(&code).await.unwrap();
+
+        assert_eq!(polls.load(Ordering::SeqCst), 3);
+        assert_eq!(creds.user_id, "4321");
+        assert_eq!(creds.auth_token, "access");
+        assert_eq!(creds.refresh_token, "refresh");
+        assert_eq!(creds.client_secret, "secret");
+    }
+
+    #[tokio::test]
+    async fn test_device_flow_denied() {
+        let base_url = mock::serve(|req| match req.path.as_str() {
+            "/oauth2/token" => twitch_error("access_denied"),
+            _ => (404, String::new()),
+        })
+        .await
+        .unwrap();
+
+        let oauth = OAuth::new("id", "secret").with_base_url(base_url);
+
+        let code = DeviceCode {
+            device_code: String::from("device"),
+            user_code: String::from("ABCDEFGH"),
+            verification_uri: String::new(),
+            expires_in: 1800,
+            interval: 0,
+        };
+
+        assert!(matches!(
+            oauth.finish_device_flow(&code).await,
+            Err(AuthError::Denied)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_flow() {
+        let base_url = mock::serve(|req| match req.path.as_str() {
+            "/oauth2/token" => {
+                assert!(req.body.contains("code=granted"));
+                assert!(req.body.contains("grant_type=authorization_code"));
+
+                tokens()
+            }
+            "/oauth2/validate" => validation(),
+            _ => (404, String::new()),
+        })
+        .await
+        .unwrap();
+
+        let oauth = OAuth::new("id", "secret").with_base_url(base_url);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let redirect_uri = format!("http://{}", listener.local_addr().unwrap());
+
+        let creds = oauth
+            .authorization_code_flow(listener, &redirect_uri, |url| {
+                let url = Url::parse(url).unwrap();
+                let state = url
+                    .query_pairs()
+                    .find(|(k, _)| k == "state")
+                    .unwrap()
+                    .1
+                    .to_string();
+
+                assert!(url.path().ends_with("/oauth2/authorize"));
+
+                // Acts as the browser being redirected back once the user logs in
+                let redirect = format!("{redirect_uri}/?code=granted&state={state}");
+                tokio::spawn(async move {
+                    reqwest::get(redirect).await.unwrap();
+                });
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(creds.user_id, "4321");
+        assert_eq!(creds.auth_token, "access");
+    }
+
+    #[tokio::test]
+    async fn test_state_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let redirect_uri = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            reqwest::get(format!("{redirect_uri}/?code=granted&state=forged"))
+                .await
+                .unwrap();
+        });
+
+        assert!(matches!(
+            receive_code(&listener, "expected").await,
+            Err(AuthError::StateMismatch)
+        ));
+    }
+}
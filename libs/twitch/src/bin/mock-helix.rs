@@ -0,0 +1,27 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+//! Serves the bundled fixtures as Twitch, for running the app without a network or an account
+
+use tokio::net::TcpListener;
+use twitch_api::mock::{self, Fixtures};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let port = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("8081"));
+
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await?;
+
+    let fixtures = Fixtures::default();
+    let base_url = mock::serve_on(listener, move |req| fixtures.reply(req))?;
+
+    println!("Serving mock Twitch at {base_url}, point FauxChat at it with:");
+    println!("    FAUXCHAT_HELIX_URL={base_url}/helix");
+    println!("    FAUXCHAT_ID_URL={base_url}");
+    println!("    TWITCH_USER_ID={}", mock::BROADCASTER_ID);
+
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
@@ -0,0 +1,417 @@
+//! The pool kept in the data dir between launches, so it is only downloaded again once it is stale
+//!
+//! Downloading again merges into the pool already there, so everyone keeps the color they were given. Edits made by
+//! hand are kept apart from the download, and made again to it every time it is loaded.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{edit::PoolEdit, helix::HelixApi, PoolSource, TwitchApiError, TwitchUser, UserPool};
+
+/// The name of the cached pool, within the data dir
+pub const FILE_NAME: &str = "pool.json";
+
+/// How long a cached pool is used before it is downloaded again, unless configured otherwise
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Error)]
+pub enum PoolCacheError {
+    #[error("Could not find the data directory to cache the pool in")]
+    NoDataDir,
+    #[error("Failed to read or write the cached pool: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The cached pool is invalid: {0}")]
+    Invalid(#[from] serde_json::Error),
+    #[error("Failed to download the pool: {0}")]
+    Download(#[from] TwitchApiError),
+    #[error("The pool can't be downloaded while offline")]
+    Offline,
+}
+
+/// A pool along with when and where it was downloaded from
+///
+/// The pool is flattened, so the file can still be read as a plain [`UserPool`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedPool {
+    /// Unix time in seconds the pool was downloaded, pools written without one are always stale
+    #[serde(default)]
+    pub fetched_at: u64,
+    #[serde(default)]
+    pub source: PoolSource,
+    #[serde(flatten)]
+    pub pool: UserPool,
+    /// Changes made by hand, in the order they were made
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<PoolEdit>,
+}
+
+/// What changed in the pool when it was downloaded again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PoolDiff {
+    pub added: usize,
+    pub removed: usize,
+    /// Users who were renamed, or whose roles changed
+    pub updated: usize,
+}
+
+/// The directory the pool is cached in
+pub fn dir() -> Result<PathBuf, PoolCacheError> {
+    let dirs = directories::ProjectDirs::from("com", "jewelexx", "FauxChat")
+        .ok_or(PoolCacheError::NoDataDir)?;
+
+    Ok(dirs.data_dir().to_path_buf())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl CachedPool {
+    #[must_use]
+    pub fn new(pool: UserPool, source: PoolSource) -> Self {
+        Self {
+            fetched_at: now(),
+            source,
+            pool,
+            edits: Vec::new(),
+        }
+    }
+
+    /// Reads the cached pool from `dir`, if one has been saved
+    pub fn load(dir: &Path) -> Result<Option<Self>, PoolCacheError> {
+        let path = dir.join(FILE_NAME);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), PoolCacheError> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(FILE_NAME), serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    /// The pool with the edits made to it, where edits that no longer apply to the download are skipped
+    #[must_use]
+    pub fn edited(&self) -> UserPool {
+        let mut pool = UserPool {
+            users: self.pool.users.clone(),
+        };
+
+        for edit in &self.edits {
+            if let Err(e) = pool.apply(edit) {
+                tracing::warn!("Skipping a pool edit that no longer applies: {e}");
+            }
+        }
+
+        pool
+    }
+
+    /// Merges a fresh download into the pool, keeping everyone's colors, and marks it as downloaded now
+    pub fn update(&mut self, fresh: UserPool, source: PoolSource) -> PoolDiff {
+        let diff = self.pool.merge(fresh);
+
+        self.fetched_at = now();
+        self.source = source;
+
+        diff
+    }
+
+    /// Whether the pool should be downloaded again, as it is older than `ttl` or from another source
+    #[must_use]
+    pub fn is_stale(&self, ttl: Duration, source: PoolSource) -> bool {
+        self.source != source || now().saturating_sub(self.fetched_at) >= ttl.as_secs()
+    }
+}
+
+impl UserPool {
+    /// Replaces the users with those in `fresh`, keeping the color of anyone already in the pool
+    pub fn merge(&mut self, fresh: UserPool) -> PoolDiff {
+        let mut previous: HashMap<String, TwitchUser> = self
+            .users
+            .drain(..)
+            .map(|user| (user.uid.clone(), user))
+            .collect();
+
+        let mut diff = PoolDiff::default();
+
+        self.users = fresh
+            .users
+            .into_iter()
+            .map(|mut user| {
+                match previous.remove(&user.uid) {
+                    Some(old) => {
+                        if old.name != user.name
+                            || old.is_mod != user.is_mod
+                            || old.is_vip != user.is_vip
+                            || old.is_sub != user.is_sub
+                        {
+                            diff.updated += 1;
+                        }
+
+                        user.color = old.color;
+                    }
+                    None => diff.added += 1,
+                }
+
+                user
+            })
+            .collect();
+
+        diff.removed = previous.len();
+
+        diff
+    }
+
+    /// Uses the pool cached in `dir` while it is fresh, otherwise downloads it again and updates the cache
+    ///
+    /// A stale pool is still used if downloading fails, so the app can start without Twitch. The pool in use is
+    /// [`CachedPool::edited`].
+    pub async fn cached(
+        api: &impl HelixApi,
+        broadcaster_id: &str,
+        source: PoolSource,
+        dir: &Path,
+        ttl: Duration,
+    ) -> Result<CachedPool, PoolCacheError> {
+        let cached = match CachedPool::load(dir) {
+            Ok(cached) => cached,
+            Err(e) => {
+                tracing::warn!("Ignoring the cached pool: {e}");
+                None
+            }
+        };
+
+        let mut cached = match cached {
+            Some(cached) if !cached.is_stale(ttl, source) => {
+                tracing::info!("Using the cached pool");
+                return Ok(cached);
+            }
+            Some(cached) => cached,
+            None => CachedPool::new(UserPool { users: Vec::new() }, source),
+        };
+
+        let fresh = match Self::download(api, broadcaster_id, source).await {
+            Ok(fresh) => fresh,
+            Err(e) if !cached.pool.users.is_empty() => {
+                tracing::warn!("Using the stale cached pool, as downloading failed: {e}");
+                return Ok(cached);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let diff = cached.update(fresh, source);
+        tracing::info!("Updated the cached pool: {diff:?}");
+
+        cached.save(dir)?;
+
+        Ok(cached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usergen::Color;
+
+    use super::*;
+    use crate::{edit::UserPatch, helix::Helix, mock};
+
+    fn user(uid: &str, name: &str, color: Color) -> TwitchUser {
+        TwitchUser {
+            color,
+            ..TwitchUser::new(name, uid)
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fauxchat-pool-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_merge_keeps_colors() {
+        let red = Color::new(255, 0, 0);
+        let blue = Color::new(0, 0, 255);
+
+        let mut pool = UserPool {
+            users: vec![user("1", "alice", red), user("2", "bob", red)],
+        };
+
+        let mut renamed = user("1", "alice_", blue);
+        renamed.is_sub = true;
+
+        let diff = pool.merge(UserPool {
+            users: vec![renamed, user("3", "carol", blue)],
+        });
+
+        assert_eq!(
+            diff,
+            PoolDiff {
+                added: 1,
+                removed: 1,
+                updated: 1,
+            }
+        );
+
+        assert_eq!(pool.users[0].name, "alice_");
+        assert!(pool.users[0].is_sub);
+        assert_eq!(pool.users[0].color, red);
+        assert_eq!(pool.users[1].color, blue);
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let mut cached = CachedPool::new(UserPool { users: Vec::new() }, PoolSource::Followers);
+
+        assert!(!cached.is_stale(DEFAULT_TTL, PoolSource::Followers));
+        assert!(cached.is_stale(DEFAULT_TTL, PoolSource::Chatters));
+        assert!(cached.is_stale(Duration::ZERO, PoolSource::Followers));
+
+        cached.fetched_at -= DEFAULT_TTL.as_secs();
+        assert!(cached.is_stale(DEFAULT_TTL, PoolSource::Followers));
+
+        // Pools saved before they were cached have no timestamp
+        let old: CachedPool = serde_json::from_str(r#"{"users":[]}"#).unwrap();
+        assert!(old.is_stale(DEFAULT_TTL, PoolSource::Followers));
+    }
+
+    #[tokio::test]
+    async fn test_cached() {
+        let dir = temp_dir("cached");
+        let helix = Helix::new(format!(
+            "{}/helix",
+            mock::serve_helix(mock::Fixtures::default()).await.unwrap()
+        ));
+
+        let downloaded = UserPool::cached(
+            &helix,
+            mock::BROADCASTER_ID,
+            PoolSource::Followers,
+            &dir,
+            DEFAULT_TTL,
+        )
+        .await
+        .unwrap();
+
+        let mut cached = CachedPool::load(&dir).unwrap().unwrap();
+        assert_eq!(cached.pool.users, downloaded.pool.users);
+
+        // A fresh cache is used as is, even if Twitch is unreachable
+        let offline = Helix::new("http://127.0.0.1:9/helix");
+        let pool = UserPool::cached(
+            &offline,
+            mock::BROADCASTER_ID,
+            PoolSource::Followers,
+            &dir,
+            DEFAULT_TTL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(pool.pool.users, downloaded.pool.users);
+
+        // Once stale it is downloaded again, without changing anyone's color
+        let color = Color::new(1, 2, 3);
+        cached.pool.users[0].color = color;
+        cached.fetched_at = 0;
+        cached.save(&dir).unwrap();
+
+        let pool = UserPool::cached(
+            &helix,
+            mock::BROADCASTER_ID,
+            PoolSource::Followers,
+            &dir,
+            DEFAULT_TTL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(pool.pool.users[0].color, color);
+        assert!(!CachedPool::load(&dir)
+            .unwrap()
+            .unwrap()
+            .is_stale(DEFAULT_TTL, PoolSource::Followers));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_edits_survive_download() {
+        let dir = temp_dir("edits");
+        let helix = Helix::new(format!(
+            "{}/helix",
+            mock::serve_helix(mock::Fixtures::default()).await.unwrap()
+        ));
+
+        let mut cached = UserPool::cached(
+            &helix,
+            mock::BROADCASTER_ID,
+            PoolSource::Followers,
+            &dir,
+            DEFAULT_TTL,
+        )
+        .await
+        .unwrap();
+
+        let first = cached.pool.users[0].uid.clone();
+        let persona = user("999", "demo_persona", Color::new(1, 2, 3));
+
+        cached.edits = vec![
+            PoolEdit::Add {
+                user: persona.clone(),
+            },
+            PoolEdit::Edit {
+                uid: first,
+                patch: UserPatch {
+                    name: Some(String::from("Renamed")),
+                    uid: Some(String::from("1000")),
+                    ..UserPatch::default()
+                },
+            },
+            // Later edits find the user by the uid they were given
+            PoolEdit::Edit {
+                uid: String::from("1000"),
+                patch: UserPatch {
+                    is_vip: Some(true),
+                    ..UserPatch::default()
+                },
+            },
+        ];
+        cached.fetched_at = 0;
+        cached.save(&dir).unwrap();
+
+        let reloaded = UserPool::cached(
+            &helix,
+            mock::BROADCASTER_ID,
+            PoolSource::Followers,
+            &dir,
+            DEFAULT_TTL,
+        )
+        .await
+        .unwrap();
+
+        // The download is merged into the pool as it was downloaded, and the edits are made again
+        assert_ne!(reloaded.fetched_at, 0);
+        assert_eq!(reloaded.edits.len(), 3);
+
+        let pool = reloaded.edited();
+        assert_eq!(pool.find("999"), Some(&persona));
+
+        let renamed = pool.find("1000").unwrap();
+        assert_eq!(renamed.name, "Renamed");
+        assert!(renamed.is_vip);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
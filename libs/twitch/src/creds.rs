@@ -1,27 +1,68 @@
 use std::{path::PathBuf, time::Duration};
 
 use anyhow::Context;
-use const_format::formatcp;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-mod decl;
+use crate::auth::{AuthError, OAuth};
 
-pub use decl::Credentials;
+pub mod store;
 
-#[derive(Debug, Deserialize)]
-pub struct AccessToken {
-    access_token: String,
-    refresh_token: String,
-}
+pub use store::Store;
 
+/// The credentials in use, which are empty until [`Credentials::init`] has loaded them
 pub static CREDENTIALS: Lazy<Mutex<Credentials>> = Lazy::new(|| Mutex::new(Credentials::default()));
 
-impl Default for Credentials {
-    fn default() -> Self {
-        Self::load().unwrap()
-    }
+/// The user id of the broadcaster when offline, as there is no Twitch account to take it from
+pub const OFFLINE_USER_ID: &str = "100000000";
+
+/// The environment variables credentials may be given in, when there is no credentials file
+const ENV_VARS: [&str; 5] = [
+    "TWITCH_CLIENT_ID",
+    "TWITCH_CLIENT_SECRET",
+    "TWITCH_USER_ID",
+    "TWITCH_AUTH_TOKEN",
+    "TWITCH_REFRESH_TOKEN",
+];
+
+#[derive(Debug, Error)]
+pub enum CredentialsError {
+    #[error(
+        "No Twitch credentials found in {0}. Run `fauxchat setup`, set the {} environment variables, or create credentials.toml following credentials.example.toml",
+        ENV_VARS.join(", ")
+    )]
+    Missing(String),
+    #[error("Could not find the data directory to store credentials in")]
+    NoDataDir,
+    #[error("Failed to read the credentials file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The credentials file is invalid: {0}")]
+    Invalid(#[from] toml::de::Error),
+    #[error("Failed to write the credentials file: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Unknown credentials store `{0}`, expected file, encrypted or keyring")]
+    UnknownStore(String),
+    #[error("The encrypted credentials need a passphrase, set FAUXCHAT_PASSPHRASE")]
+    PassphraseRequired,
+    #[error("Failed to decrypt the credentials, the passphrase is wrong or the file is damaged")]
+    Decrypt,
+    #[error("The OS secret service failed: {0}")]
+    Keyring(String),
+    #[error(
+        "The OS secret service is not available, FauxChat was built without the keyring feature"
+    )]
+    KeyringUnavailable,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Credentials {
+    pub client_id: String,
+    pub client_secret: String,
+    pub user_id: String,
+    pub auth_token: String,
+    pub refresh_token: String,
 }
 
 impl Credentials {
@@ -30,10 +71,11 @@ impl Credentials {
         CREDENTIALS.lock().clone()
     }
 
+    /// Loads the credentials, and refreshes the token if it is close to expiring
     pub async fn init() -> anyhow::Result<()> {
-        let mut creds = Credentials::read();
+        let mut creds = Credentials::load()?;
 
-        dbg!(&creds);
+        *CREDENTIALS.lock() = creds.clone();
 
         if !matches!(creds.remain_30().await, Ok(false)) {
             tracing::debug!("Attempting to refresh token");
@@ -51,122 +93,108 @@ impl Credentials {
         Ok(())
     }
 
-    pub fn load() -> anyhow::Result<Self> {
-        use std::{fs::File, io::Read};
+    /// Loads the credentials from the selected [`Store`], falling back to the environment, which is then saved for next time
+    ///
+    /// Credentials left in `credentials.toml` are moved into the store if it is another store.
+    pub fn load() -> Result<Self, CredentialsError> {
+        let dir = Self::data_dir()?;
+        let store = Store::current()?;
 
-        let creds_path = Self::get_path()?;
-        if creds_path.exists() {
-            let mut file_contents = String::new();
+        if let Some(creds) = store.load(&dir)? {
+            return Ok(creds);
+        }
 
-            File::open(creds_path)?.read_to_string(&mut file_contents)?;
+        if let Some(creds) = store.migrate(&dir)? {
+            return Ok(creds);
+        }
 
-            Ok(toml::from_str(&file_contents)?)
-        } else {
-            // TODO: Don't include credentials at build time
-            let client_id = env!("TWITCH_CLIENT_ID").to_string();
-            let client_secret = env!("TWITCH_CLIENT_SECRET").to_string();
-            let user_id = env!("TWITCH_USER_ID").to_string();
-            let auth_token = env!("TWITCH_AUTH_TOKEN").to_string();
-            let refresh_token = env!("TWITCH_REFRESH_TOKEN").to_string();
+        let creds =
+            Self::from_env().ok_or_else(|| CredentialsError::Missing(Self::location(store)))?;
 
-            let creds = Credentials {
-                client_id,
-                client_secret,
-                user_id,
-                auth_token,
-                refresh_token,
-            };
+        creds.save()?;
 
-            creds.save()?;
+        Ok(creds)
+    }
 
-            Ok(creds)
+    /// Credentials for running without Twitch, which only identify the broadcaster
+    #[must_use]
+    pub fn offline() -> Self {
+        Self {
+            user_id: OFFLINE_USER_ID.to_string(),
+            ..Self::default()
         }
     }
 
-    pub fn get_path() -> anyhow::Result<PathBuf> {
-        use std::fs::create_dir_all;
+    /// Reads the credentials from the environment, if every variable is set
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let [client_id, client_secret, user_id, auth_token, refresh_token] =
+            ENV_VARS.map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()));
+
+        Some(Self {
+            client_id: client_id?,
+            client_secret: client_secret?,
+            user_id: user_id?,
+            auth_token: auth_token?,
+            refresh_token: refresh_token?,
+        })
+    }
 
+    /// The directory credentials files are kept in
+    pub fn data_dir() -> Result<PathBuf, CredentialsError> {
         let dir = directories::ProjectDirs::from("com", "jewelexx", "FauxChat")
-            .unwrap_or_else(|| unimplemented!());
+            .ok_or(CredentialsError::NoDataDir)?;
 
         let data_dir = dir.data_dir();
 
         if !data_dir.exists() {
-            create_dir_all(data_dir)?;
+            std::fs::create_dir_all(data_dir)?;
         }
 
-        Ok(data_dir.join("credentials.toml"))
+        Ok(data_dir.to_path_buf())
     }
 
-    pub async fn expires_in(&self) -> anyhow::Result<Duration> {
-        let response: serde_json::Value = crate::CLIENT
-            .get("https://id.twitch.tv/oauth2/validate")
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        dbg!(&response);
-
-        let status = response["status"].as_u64().unwrap_or(200);
-
-        if !(200..300).contains(&status) {
-            anyhow::bail!("Found non 200 status: {}", status);
+    /// Where the [`Store`] keeps the credentials, for telling the user
+    #[must_use]
+    pub fn location(store: Store) -> String {
+        match Self::data_dir().map(|dir| store.path(&dir)) {
+            Ok(Some(path)) => path.display().to_string(),
+            Ok(None) => String::from("the OS secret service"),
+            Err(_) => format!("the {store} store"),
         }
+    }
+
+    /// The application these credentials log in as
+    #[must_use]
+    pub fn oauth(&self) -> OAuth {
+        OAuth::new(&self.client_id, &self.client_secret)
+    }
 
-        let expires_in = response["expires_in"].as_u64().ok_or_else(|| {
-            anyhow::anyhow!("Could not parse expires_in from response: {:?}", response)
-        })?;
+    pub async fn expires_in(&self) -> Result<Duration, AuthError> {
+        let validation = self.oauth().validate(&self.auth_token).await?;
 
-        Ok(Duration::from_secs(expires_in))
+        Ok(Duration::from_secs(validation.expires_in))
     }
 
-    pub async fn remain_30(&self) -> anyhow::Result<bool> {
+    pub async fn remain_30(&self) -> Result<bool, AuthError> {
         let expires_in = self.expires_in().await?;
 
         Ok(expires_in < Duration::from_secs(30 * 60))
     }
 
     pub async fn refresh(&mut self) -> anyhow::Result<()> {
-        const CLIENT_ID: &str = env!("TWITCH_CLIENT_ID");
-        const CLIENT_SECRET: &str = env!("TWITCH_CLIENT_SECRET");
-        const REFRESH_TOKEN: &str = env!("TWITCH_REFRESH_TOKEN");
-
-        const REFRESH_URL: &str = formatcp!(
-            "https://id.twitch.tv/oauth2/token?client_id={client_id}&client_secret={client_secret}&grant_type=refresh_token&refresh_token={refresh_token}",
-            client_id = CLIENT_ID,
-            client_secret = CLIENT_SECRET,
-            refresh_token = REFRESH_TOKEN,
-        );
-
-        tracing::info!("Refreshing!!!");
-
-        let resp: AccessToken = reqwest::Client::new()
-            .post(REFRESH_URL)
-            .send()
-            .await?
-            .json()
-            .await?;
+        tracing::info!("Refreshing the Twitch API token");
 
-        self.auth_token = resp.access_token;
-
-        self.refresh_token = resp.refresh_token;
+        *self = self.oauth().refresh(self).await?;
 
         self.save()?;
 
         Ok(())
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        use std::{fs::File, io::Write};
-
-        let path = Self::get_path()?;
-
-        let creds_str = toml::to_string(&self)?;
-
-        let mut file = File::create(path)?;
-
-        file.write_all(creds_str.as_bytes())?;
+    /// Saves the credentials to the selected [`Store`], and uses them from now on
+    pub fn save(&self) -> Result<(), CredentialsError> {
+        Store::current()?.save(&Self::data_dir()?, self)?;
 
         *CREDENTIALS.lock() = self.clone();
 
@@ -1,10 +0,0 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Credentials {
-    pub client_id: String,
-    pub client_secret: String,
-    pub user_id: String,
-    pub auth_token: String,
-    pub refresh_token: String,
-}
@@ -0,0 +1,428 @@
+//! Where the credentials are kept between runs
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use super::{Credentials, CredentialsError};
+
+/// The store set with [`Store::select`], otherwise the one from `FAUXCHAT_CREDENTIALS_STORE` is used
+pub static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+/// The passphrase for the encrypted store, from `FAUXCHAT_PASSPHRASE` unless set with [`Store::set_passphrase`]
+static PASSPHRASE: Lazy<Mutex<Option<String>>> =
+    Lazy::new(|| Mutex::new(std::env::var("FAUXCHAT_PASSPHRASE").ok()));
+
+/// Written at the start of encrypted files, to tell them apart from anything else
+const MAGIC: &[u8] = b"FAUXCHAT-CREDENTIALS-1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// The name credentials are kept under in the OS secret service
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "FauxChat";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "twitch";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Store {
+    /// A TOML file only the current user can read
+    #[default]
+    File,
+    /// A file encrypted with a passphrase
+    Encrypted,
+    /// The OS secret service, only available with the `keyring` feature
+    Keyring,
+}
+
+impl FromStr for Store {
+    type Err = CredentialsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(Self::File),
+            "encrypted" => Ok(Self::Encrypted),
+            "keyring" => Ok(Self::Keyring),
+            _ => Err(CredentialsError::UnknownStore(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Encrypted => write!(f, "encrypted"),
+            Self::Keyring => write!(f, "keyring"),
+        }
+    }
+}
+
+impl Store {
+    /// The store in use, which fails if `FAUXCHAT_CREDENTIALS_STORE` names a store that doesn't exist
+    pub fn current() -> Result<Self, CredentialsError> {
+        let selected = *STORE.lock();
+
+        Self::resolve(
+            selected,
+            std::env::var("FAUXCHAT_CREDENTIALS_STORE").ok().as_deref(),
+        )
+    }
+
+    /// The selected store, otherwise the one named in the environment
+    fn resolve(selected: Option<Self>, env: Option<&str>) -> Result<Self, CredentialsError> {
+        match (selected, env) {
+            (Some(store), _) => Ok(store),
+            (None, Some(store)) => store.parse(),
+            (None, None) => Ok(Self::default()),
+        }
+    }
+
+    /// Uses this store for loading and saving credentials from now on
+    pub fn select(self) {
+        *STORE.lock() = Some(self);
+    }
+
+    pub fn set_passphrase(passphrase: impl Into<String>) {
+        *PASSPHRASE.lock() = Some(passphrase.into());
+    }
+
+    /// Where the store keeps the credentials, which is nowhere on disk for the keyring
+    #[must_use]
+    pub fn path(self, dir: &Path) -> Option<PathBuf> {
+        match self {
+            Self::File => Some(dir.join("credentials.toml")),
+            Self::Encrypted => Some(dir.join("credentials.enc")),
+            Self::Keyring => None,
+        }
+    }
+
+    /// Loads the credentials, or [`None`] if there are none in this store
+    pub fn load(self, dir: &Path) -> Result<Option<Credentials>, CredentialsError> {
+        match self {
+            Self::File => {
+                let path = dir.join("credentials.toml");
+
+                if !path.exists() {
+                    return Ok(None);
+                }
+
+                // Files written before permissions were restricted are fixed up when read
+                restrict_permissions(&path)?;
+
+                Ok(Some(toml::from_str(&std::fs::read_to_string(path)?)?))
+            }
+            Self::Encrypted => {
+                let path = dir.join("credentials.enc");
+
+                if !path.exists() {
+                    return Ok(None);
+                }
+
+                let plaintext = decrypt(&std::fs::read(path)?, &passphrase()?)?;
+
+                Ok(Some(toml::from_str(&plaintext)?))
+            }
+            Self::Keyring => keyring::load(),
+        }
+    }
+
+    pub fn save(self, dir: &Path, creds: &Credentials) -> Result<(), CredentialsError> {
+        let toml = toml::to_string(creds)?;
+
+        match self {
+            Self::File => write_private(&dir.join("credentials.toml"), toml.as_bytes()),
+            Self::Encrypted => write_private(
+                &dir.join("credentials.enc"),
+                &encrypt(&toml, &passphrase()?),
+            ),
+            Self::Keyring => keyring::save(&toml),
+        }
+    }
+
+    pub fn delete(self, dir: &Path) -> Result<(), CredentialsError> {
+        match self.path(dir) {
+            Some(path) if path.exists() => Ok(std::fs::remove_file(path)?),
+            Some(_) => Ok(()),
+            None => keyring::delete(),
+        }
+    }
+
+    /// Moves credentials from the plain `credentials.toml` into this store, if it is another store
+    ///
+    /// Returns the credentials that were moved.
+    pub fn migrate(self, dir: &Path) -> Result<Option<Credentials>, CredentialsError> {
+        if self == Self::File {
+            return Ok(None);
+        }
+
+        let Some(creds) = Self::File.load(dir)? else {
+            return Ok(None);
+        };
+
+        self.save(dir, &creds)?;
+        Self::File.delete(dir)?;
+
+        tracing::info!("Moved credentials.toml into the {self} store");
+
+        Ok(Some(creds))
+    }
+}
+
+fn passphrase() -> Result<String, CredentialsError> {
+    PASSPHRASE
+        .lock()
+        .clone()
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or(CredentialsError::PassphraseRequired)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .expect("salt and key are valid lengths");
+
+    ChaCha20Poly1305::new(&key.into())
+}
+
+fn encrypt(plaintext: &str, passphrase: &str) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+
+    let mut salt = [0; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher(passphrase, &salt)
+        .encrypt(&nonce.into(), plaintext.as_bytes())
+        .expect("encrypting into memory never fails");
+
+    [MAGIC, &salt, &nonce, &ciphertext].concat()
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> Result<String, CredentialsError> {
+    let data = data
+        .strip_prefix(MAGIC)
+        .filter(|data| data.len() > SALT_LEN + NONCE_LEN)
+        .ok_or(CredentialsError::Decrypt)?;
+
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+    let plaintext = cipher(passphrase, salt)
+        .decrypt(nonce.into(), ciphertext)
+        .map_err(|_| CredentialsError::Decrypt)?;
+
+    String::from_utf8(plaintext).map_err(|_| CredentialsError::Decrypt)
+}
+
+/// Writes a file only the current user can read
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), CredentialsError> {
+    #[cfg(unix)]
+    {
+        use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+        // The mode only applies to new files, existing ones are restricted first
+        if path.exists() {
+            restrict_permissions(path)?;
+        }
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?
+            .write_all(contents)?;
+    }
+
+    #[cfg(not(unix))]
+    std::fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<(), CredentialsError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+
+    if permissions.mode() & 0o077 != 0 {
+        permissions.set_mode(0o600);
+        std::fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn restrict_permissions(_: &Path) -> Result<(), CredentialsError> {
+    Ok(())
+}
+
+#[cfg(feature = "keyring")]
+mod keyring {
+    use super::{Credentials, CredentialsError, KEYRING_SERVICE, KEYRING_USER};
+
+    fn entry() -> Result<keyring::Entry, CredentialsError> {
+        keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+            .map_err(|e| CredentialsError::Keyring(e.to_string()))
+    }
+
+    pub fn load() -> Result<Option<Credentials>, CredentialsError> {
+        match entry()?.get_password() {
+            Ok(toml) => Ok(Some(toml::from_str(&toml)?)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(CredentialsError::Keyring(e.to_string())),
+        }
+    }
+
+    pub fn save(toml: &str) -> Result<(), CredentialsError> {
+        entry()?
+            .set_password(toml)
+            .map_err(|e| CredentialsError::Keyring(e.to_string()))
+    }
+
+    pub fn delete() -> Result<(), CredentialsError> {
+        match entry()?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(CredentialsError::Keyring(e.to_string())),
+        }
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+mod keyring {
+    use super::{Credentials, CredentialsError};
+
+    pub fn load() -> Result<Option<Credentials>, CredentialsError> {
+        Err(CredentialsError::KeyringUnavailable)
+    }
+
+    pub fn save(_: &str) -> Result<(), CredentialsError> {
+        Err(CredentialsError::KeyringUnavailable)
+    }
+
+    pub fn delete() -> Result<(), CredentialsError> {
+        Err(CredentialsError::KeyringUnavailable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("fauxchat-creds-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn creds() -> Credentials {
+        Credentials {
+            client_id: String::from("id"),
+            client_secret: String::from("secret"),
+            user_id: String::from("1234"),
+            auth_token: String::from("token"),
+            refresh_token: String::from("refresh"),
+        }
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let data = encrypt("client_secret = \"secret\"", "hunter2");
+
+        assert!(!data.windows(6).any(|window| window == b"secret"));
+        assert_eq!(
+            decrypt(&data, "hunter2").unwrap(),
+            "client_secret = \"secret\""
+        );
+        assert!(matches!(
+            decrypt(&data, "hunter3"),
+            Err(CredentialsError::Decrypt)
+        ));
+        assert!(matches!(
+            decrypt(b"not encrypted", "hunter2"),
+            Err(CredentialsError::Decrypt)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("file");
+        let path = dir.join("credentials.toml");
+
+        // As written by older versions, readable by everyone
+        std::fs::write(&path, toml::to_string(&creds()).unwrap()).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        assert_eq!(
+            Store::File.load(&dir).unwrap().unwrap().client_secret,
+            "secret"
+        );
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_store() {
+        assert!(matches!(
+            Store::resolve(None, Some("vault")),
+            Err(CredentialsError::UnknownStore(store)) if store == "vault"
+        ));
+        assert_eq!(
+            Store::resolve(None, Some("Keyring")).unwrap(),
+            Store::Keyring
+        );
+        assert_eq!(Store::resolve(None, None).unwrap(), Store::File);
+
+        // The config wins over the environment
+        assert_eq!(
+            Store::resolve(Some(Store::Encrypted), Some("vault")).unwrap(),
+            Store::Encrypted
+        );
+    }
+
+    #[test]
+    fn test_migrate_to_encrypted() {
+        let dir = temp_dir("migrate");
+
+        Store::File.save(&dir, &creds()).unwrap();
+        Store::set_passphrase("hunter2");
+
+        let migrated = Store::Encrypted.migrate(&dir).unwrap().unwrap();
+
+        assert_eq!(migrated.auth_token, "token");
+        assert!(!dir.join("credentials.toml").exists());
+        assert_eq!(
+            Store::Encrypted.load(&dir).unwrap().unwrap().refresh_token,
+            "refresh"
+        );
+
+        // Nothing left to move
+        assert!(Store::Encrypted.migrate(&dir).unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
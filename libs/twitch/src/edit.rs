@@ -0,0 +1,253 @@
+//! Changes to the users in a pool, for crafting the chatters of a demo by hand
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use usergen::Color;
+
+use crate::{Subscription, TwitchUser, UserPool};
+
+#[derive(Debug, Error)]
+pub enum PoolEditError {
+    #[error("No user with the uid {0} in the pool")]
+    NotFound(String),
+    #[error("A user with the uid {0} is already in the pool")]
+    DuplicateUid(String),
+    #[error("A user named {0} is already in the pool")]
+    DuplicateName(String),
+    #[error("Users need a name and a uid")]
+    Empty,
+    #[error("The last user in the pool can't be removed, messages need someone to send them")]
+    LastUser,
+}
+
+/// The changes to make to a user, where anything unset is left as it is
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserPatch {
+    pub name: Option<String>,
+    pub uid: Option<String>,
+    pub color: Option<Color>,
+    pub is_mod: Option<bool>,
+    pub is_vip: Option<bool>,
+    pub is_sub: Option<bool>,
+    pub subscription: Option<Subscription>,
+}
+
+/// A change made to a pool by hand, which can be made again to the pool once it has been downloaded again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PoolEdit {
+    Add { user: TwitchUser },
+    Edit { uid: String, patch: UserPatch },
+    Remove { uid: String },
+}
+
+impl UserPatch {
+    fn apply(self, user: &mut TwitchUser) {
+        if let Some(name) = self.name {
+            user.name = name;
+        }
+
+        if let Some(uid) = self.uid {
+            user.uid = uid;
+        }
+
+        user.color = self.color.unwrap_or(user.color);
+        user.is_mod = self.is_mod.unwrap_or(user.is_mod);
+        user.is_vip = self.is_vip.unwrap_or(user.is_vip);
+        user.is_sub = self.is_sub.unwrap_or(user.is_sub);
+        user.subscription = self.subscription.unwrap_or(user.subscription);
+    }
+}
+
+impl UserPool {
+    #[must_use]
+    pub fn find(&self, uid: &str) -> Option<&TwitchUser> {
+        self.users.iter().find(|user| user.uid == uid)
+    }
+
+    /// Everyone whose name or uid contains the query, ignoring case
+    #[must_use]
+    pub fn search(&self, query: &str) -> Vec<&TwitchUser> {
+        let query = query.to_lowercase();
+
+        self.users
+            .iter()
+            .filter(|user| user.name.to_lowercase().contains(&query) || user.uid.contains(&query))
+            .collect()
+    }
+
+    /// Checks the user can be in the pool, ignoring whoever has the uid `except`
+    fn check(&self, user: &TwitchUser, except: Option<&str>) -> Result<(), PoolEditError> {
+        if user.name.trim().is_empty() || user.uid.trim().is_empty() {
+            return Err(PoolEditError::Empty);
+        }
+
+        let others = || {
+            self.users
+                .iter()
+                .filter(move |other| Some(other.uid.as_str()) != except)
+        };
+
+        if others().any(|other| other.uid == user.uid) {
+            return Err(PoolEditError::DuplicateUid(user.uid.clone()));
+        }
+
+        if others().any(|other| other.name.eq_ignore_ascii_case(&user.name)) {
+            return Err(PoolEditError::DuplicateName(user.name.clone()));
+        }
+
+        Ok(())
+    }
+
+    pub fn add(&mut self, user: TwitchUser) -> Result<(), PoolEditError> {
+        self.check(&user, None)?;
+
+        self.users.push(user);
+
+        Ok(())
+    }
+
+    fn position(&self, uid: &str) -> Result<usize, PoolEditError> {
+        self.users
+            .iter()
+            .position(|user| user.uid == uid)
+            .ok_or_else(|| PoolEditError::NotFound(uid.to_string()))
+    }
+
+    /// Changes the user with the uid, returning them as they are now
+    pub fn edit(&mut self, uid: &str, patch: UserPatch) -> Result<TwitchUser, PoolEditError> {
+        let index = self.position(uid)?;
+
+        let mut user = self.users[index].clone();
+        patch.apply(&mut user);
+
+        self.check(&user, Some(uid))?;
+
+        self.users[index] = user.clone();
+
+        Ok(user)
+    }
+
+    /// Makes the edit, returning the user it was made to
+    pub fn apply(&mut self, edit: &PoolEdit) -> Result<TwitchUser, PoolEditError> {
+        match edit {
+            PoolEdit::Add { user } => self.add(user.clone()).map(|()| user.clone()),
+            PoolEdit::Edit { uid, patch } => self.edit(uid, patch.clone()),
+            PoolEdit::Remove { uid } => self.remove(uid),
+        }
+    }
+
+    pub fn remove(&mut self, uid: &str) -> Result<TwitchUser, PoolEditError> {
+        let index = self.position(uid)?;
+
+        if self.users.len() == 1 {
+            return Err(PoolEditError::LastUser);
+        }
+
+        Ok(self.users.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> UserPool {
+        UserPool {
+            users: vec![
+                TwitchUser::new("SomeOne", "1"),
+                TwitchUser::new("another", "2"),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let pool = pool();
+
+        assert_eq!(pool.search("someone").len(), 1);
+        assert_eq!(pool.search("o").len(), 2);
+        assert_eq!(pool.search("2")[0].name, "another");
+        assert!(pool.search("nobody").is_empty());
+    }
+
+    #[test]
+    fn test_add() {
+        let mut pool = pool();
+
+        pool.add(TwitchUser::new("persona", "3")).unwrap();
+        assert_eq!(pool.find("3").unwrap().name, "persona");
+
+        assert!(matches!(
+            pool.add(TwitchUser::new("other", "3")),
+            Err(PoolEditError::DuplicateUid(_))
+        ));
+        assert!(matches!(
+            pool.add(TwitchUser::new("someone", "4")),
+            Err(PoolEditError::DuplicateName(_))
+        ));
+        assert!(matches!(
+            pool.add(TwitchUser::new(" ", "5")),
+            Err(PoolEditError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut pool = pool();
+
+        let edited = pool
+            .edit(
+                "1",
+                UserPatch {
+                    name: Some(String::from("Renamed")),
+                    uid: Some(String::from("10")),
+                    color: Some(Color::new(1, 2, 3)),
+                    is_mod: Some(true),
+                    ..UserPatch::default()
+                },
+            )
+            .unwrap();
+
+        assert_eq!(pool.find("10"), Some(&edited));
+        assert!(pool.find("1").is_none());
+        assert_eq!(edited.name, "Renamed");
+        assert_eq!(edited.color, Color::new(1, 2, 3));
+        assert!(edited.is_mod && !edited.is_vip);
+
+        // Keeping their own name is fine, taking someone else's is not
+        pool.edit(
+            "10",
+            UserPatch {
+                name: Some(String::from("renamed")),
+                ..UserPatch::default()
+            },
+        )
+        .unwrap();
+
+        let taken = UserPatch {
+            uid: Some(String::from("2")),
+            ..UserPatch::default()
+        };
+        assert!(matches!(
+            pool.edit("10", taken),
+            Err(PoolEditError::DuplicateUid(_))
+        ));
+        assert!(matches!(
+            pool.edit("3", UserPatch::default()),
+            Err(PoolEditError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut pool = pool();
+
+        assert_eq!(pool.remove("1").unwrap().name, "SomeOne");
+        assert_eq!(pool.users.len(), 1);
+        assert!(matches!(pool.remove("1"), Err(PoolEditError::NotFound(_))));
+        assert!(matches!(pool.remove("2"), Err(PoolEditError::LastUser)));
+        assert_eq!(pool.users.len(), 1);
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::auth::AuthError;
+
+#[derive(Debug, Error)]
+pub enum TwitchApiError {
+    #[error("Not allowed to use {endpoint}: {message}")]
+    Auth { endpoint: String, message: String },
+    #[error("Rate limited by Twitch while using {endpoint}, try again in {}s", retry_after.as_secs())]
+    RateLimited {
+        endpoint: String,
+        retry_after: Duration,
+    },
+    #[error("{endpoint} was not found: {message}")]
+    NotFound { endpoint: String, message: String },
+    #[error("Failed to reach Twitch: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Unexpected response from {endpoint}: {source}")]
+    Decode {
+        endpoint: String,
+        source: serde_json::Error,
+    },
+    #[error("Twitch returned {status} for {endpoint}: {message}")]
+    Status {
+        endpoint: String,
+        status: u16,
+        message: String,
+    },
+}
+
+impl TwitchApiError {
+    /// Whether trying again later might work
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } => true,
+            Self::Transport(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            Self::Status { status, .. } => *status >= 500,
+            Self::Auth { .. } | Self::NotFound { .. } | Self::Decode { .. } => false,
+        }
+    }
+}
+
+/// Failing to refresh the token means the request cannot be authorized
+impl From<AuthError> for TwitchApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Http(e) => Self::Transport(e),
+            e => Self::Auth {
+                endpoint: String::from("oauth2/token"),
+                message: e.to_string(),
+            },
+        }
+    }
+}
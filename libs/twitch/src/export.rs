@@ -0,0 +1,572 @@
+//! Exports of recordings into formats used outside of fauxchat, such as subtitles for video mockups
+//!
+//! All exports are timed from the start of the recording, using the users that were recorded with each message.
+
+use std::{fmt::Write, str::FromStr, time::Duration};
+
+use thiserror::Error;
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    import::{ChatDownload, Comment, CommentBadge, CommentMessage, Commenter, Emoticon, Streamer},
+    irc::IrcMessage,
+    recording::RecordedEvent,
+};
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Unknown export format {0:?}, expected one of srt, ass, json or csv")]
+    UnknownFormat(String),
+    #[error("Failed to serialize chat: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Srt,
+    Ass,
+    /// The JSON written by VOD chat downloaders, which can be imported again
+    VodJson,
+    Csv,
+}
+
+impl ExportFormat {
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::Ass => "ass",
+            Self::VodJson => "json",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "srt" => Ok(Self::Srt),
+            "ass" => Ok(Self::Ass),
+            "json" | "vod" => Ok(Self::VodJson),
+            "csv" => Ok(Self::Csv),
+            _ => Err(ExportError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// How chat is laid out when exported as subtitles
+#[derive(Debug, Clone)]
+pub struct SubtitleOptions {
+    /// The most messages shown at once
+    pub lines: usize,
+    /// How long a message stays on screen
+    pub linger: Duration,
+}
+
+impl Default for SubtitleOptions {
+    fn default() -> Self {
+        Self {
+            lines: 5,
+            linger: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Exports the events in the given format
+pub fn export(
+    events: &[RecordedEvent],
+    format: ExportFormat,
+    options: &SubtitleOptions,
+) -> Result<String, ExportError> {
+    match format {
+        ExportFormat::Srt => Ok(to_srt(events, options)),
+        ExportFormat::Ass => Ok(to_ass(events, options)),
+        ExportFormat::VodJson => to_vod_json(events),
+        ExportFormat::Csv => Ok(to_csv(events)),
+    }
+}
+
+/// The recorded IRC message, if it is still valid
+fn irc(event: &RecordedEvent) -> Option<IrcMessage> {
+    event.irc_message().ok()
+}
+
+/// The badges the user had when sending the message, as `(name, version)` pairs
+fn badges(event: &RecordedEvent) -> Vec<(String, String)> {
+    if let Some(badges) = irc(event).and_then(|msg| msg.tags.get("badges").map(str::to_string)) {
+        return badges
+            .split(',')
+            .filter(|badge| !badge.is_empty())
+            .map(|badge| {
+                let (name, version) = badge.split_once('/').unwrap_or((badge, "1"));
+                (name.to_string(), version.to_string())
+            })
+            .collect();
+    }
+
+    // Falls back to the roles of the user, for events that were not recorded with tags
+    let user = &event.user;
+
+    [
+        (user.is_mod, "moderator"),
+        (user.is_vip, "vip"),
+        (user.is_sub, "subscriber"),
+    ]
+    .into_iter()
+    .filter(|(has, _)| *has)
+    .map(|(_, name)| (name.to_string(), String::from("1")))
+    .collect()
+}
+
+/// A span of time during which the same messages are shown
+struct Cue<'a> {
+    start: u64,
+    end: u64,
+    messages: &'a [RecordedEvent],
+}
+
+/// Splits the events into cues, each showing the latest messages until the next one arrives or they expire
+fn cues<'a>(events: &'a [RecordedEvent], options: &SubtitleOptions) -> Vec<Cue<'a>> {
+    let linger = u64::try_from(options.linger.as_millis()).unwrap_or(u64::MAX);
+    let lines = options.lines.max(1);
+
+    let mut cues = Vec::new();
+
+    for (i, event) in events.iter().enumerate() {
+        let start = event.elapsed;
+        let expires = start.saturating_add(linger);
+        let end = events
+            .get(i + 1)
+            .map_or(expires, |next| next.elapsed.min(expires));
+
+        // Messages sent at the same time are shown together in the next cue
+        if end <= start {
+            continue;
+        }
+
+        let first = events[..=i]
+            .iter()
+            .position(|shown| shown.elapsed.saturating_add(linger) > start)
+            .unwrap_or(i)
+            .max((i + 1).saturating_sub(lines));
+
+        cues.push(Cue {
+            start,
+            end,
+            messages: &events[first..=i],
+        });
+    }
+
+    cues
+}
+
+fn split_millis(millis: u64) -> (u64, u64, u64, u64) {
+    (
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1_000 % 60,
+        millis % 1_000,
+    )
+}
+
+/// Formats a time as `HH:MM:SS,mmm`
+fn srt_time(millis: u64) -> String {
+    let (hours, minutes, seconds, millis) = split_millis(millis);
+
+    format!("{hours:02}:{minutes:02}:{seconds:02},{millis:03}")
+}
+
+/// Formats a time as `H:MM:SS.cc`
+fn ass_time(millis: u64) -> String {
+    let (hours, minutes, seconds, millis) = split_millis(millis);
+
+    format!("{hours}:{minutes:02}:{seconds:02}.{:02}", millis / 10)
+}
+
+/// Exports the events as `SubRip` subtitles
+#[must_use]
+pub fn to_srt(events: &[RecordedEvent], options: &SubtitleOptions) -> String {
+    let mut srt = String::new();
+
+    for (i, cue) in cues(events, options).iter().enumerate() {
+        let _ = writeln!(srt, "{}", i + 1);
+        let _ = writeln!(srt, "{} --> {}", srt_time(cue.start), srt_time(cue.end));
+
+        // A blank line would end the cue early, so messages are kept to a single line
+        for event in cue.messages {
+            let _ = writeln!(srt, "{}: {}", event.user.name, single_line(&event.message));
+        }
+
+        srt.push('\n');
+    }
+
+    srt
+}
+
+/// Joins the lines of the text with spaces, leaving out any that are blank
+fn single_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Escapes text so that it is not read as an override block or code, and keeps it on a single line
+///
+/// ASS has no escape for a backslash, so a word joiner is put after each to stop `\N` or `\h` being read as codes.
+fn ass_escape(text: &str) -> String {
+    single_line(text)
+        .replace('\\', "\\\u{2060}")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+}
+
+/// Exports the events as Advanced `SubStation` Alpha subtitles, with each name in the user's color
+#[must_use]
+pub fn to_ass(events: &[RecordedEvent], options: &SubtitleOptions) -> String {
+    let mut ass = String::from(
+        "[Script Info]
+ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+WrapStyle: 0
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Chat,Arial,36,&H00FFFFFF,&H00FFFFFF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,0,1,40,40,40,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+",
+    );
+
+    for cue in cues(events, options) {
+        let text = cue
+            .messages
+            .iter()
+            .map(|event| {
+                // ASS colors are written as BGR
+                let rgb = format!("{:X}", event.user.color);
+                let bgr = format!("{}{}{}", &rgb[4..6], &rgb[2..4], &rgb[0..2]);
+
+                format!(
+                    "{{\\b1\\c&H{bgr}&}}{}{{\\r}}: {}",
+                    ass_escape(&event.user.name),
+                    ass_escape(&event.message)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\\N");
+
+        let _ = writeln!(
+            ass,
+            "Dialogue: 0,{},{},Chat,,0,0,0,,{text}",
+            ass_time(cue.start),
+            ass_time(cue.end)
+        );
+    }
+
+    ass
+}
+
+/// Quotes a CSV field when needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Exports the events as CSV, with a header row
+#[must_use]
+pub fn to_csv(events: &[RecordedEvent]) -> String {
+    let mut csv = String::from("timestamp,elapsed,user,message,badges\n");
+
+    for event in events {
+        let badges = badges(event)
+            .into_iter()
+            .map(|(name, version)| format!("{name}/{version}"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let _ = writeln!(
+            csv,
+            "{},{}.{:03},{},{},{}",
+            format_utc_millis(event.timestamp).unwrap_or_default(),
+            event.elapsed / 1_000,
+            event.elapsed % 1_000,
+            csv_field(&event.user.name),
+            csv_field(&event.message),
+            csv_field(&badges)
+        );
+    }
+
+    csv
+}
+
+/// Reads the emotes out of an `emotes` tag, such as `25:0-4,6-10/1902:12-16`
+fn emoticons(tag: &str) -> Vec<Emoticon> {
+    tag.split('/')
+        .filter_map(|emote| emote.split_once(':'))
+        .flat_map(|(id, ranges)| {
+            ranges.split(',').filter_map(move |range| {
+                let (begin, end) = range.split_once('-')?;
+
+                Some(Emoticon {
+                    id: id.to_string(),
+                    begin: begin.parse().ok()?,
+                    end: end.parse().ok()?,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Exports the events in the format written by VOD chat downloaders, so that they can be used by the same tools
+#[allow(clippy::cast_precision_loss)]
+pub fn to_vod_json(events: &[RecordedEvent]) -> Result<String, ExportError> {
+    let room_id = events
+        .iter()
+        .find_map(|event| irc(event)?.tags.get("room-id").map(str::to_string));
+
+    let streamer = events.first().map(|event| Streamer {
+        name: event.channel.trim_start_matches('#').to_string(),
+        id: room_id.clone().unwrap_or_default().into(),
+    });
+
+    let comments = events
+        .iter()
+        .enumerate()
+        .map(|(i, event)| {
+            let msg = irc(event);
+            let tag = |key: &str| {
+                msg.as_ref()
+                    .and_then(|msg| msg.tags.get(key))
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+            };
+
+            let login = msg
+                .as_ref()
+                .and_then(|msg| msg.prefix.as_deref()?.split('!').next().map(str::to_string))
+                .unwrap_or_else(|| event.user.name.to_lowercase());
+
+            Comment {
+                id: tag("id").unwrap_or_else(|| format!("fauxchat-{i}")),
+                created_at: format_utc_millis(event.timestamp),
+                content_offset_seconds: event.elapsed as f64 / 1_000.0,
+                channel_id: room_id.clone(),
+                commenter: Commenter {
+                    id: event.user.uid.clone(),
+                    name: login,
+                    display_name: event.user.name.clone(),
+                },
+                message: CommentMessage {
+                    body: event.message.clone(),
+                    user_color: Some(format!("#{:X}", event.user.color)),
+                    user_badges: badges(event)
+                        .into_iter()
+                        .map(|(id, version)| CommentBadge { id, version })
+                        .collect(),
+                    emoticons: tag("emotes").map(|tag| emoticons(&tag)).unwrap_or_default(),
+                },
+            }
+        })
+        .collect();
+
+    Ok(serde_json::to_string_pretty(&ChatDownload {
+        streamer,
+        comments,
+    })?)
+}
+
+/// Formats unix milliseconds as an RFC 3339 UTC time, such as `2023-11-04T18:30:12.345Z`
+///
+/// Returns [`None`] for times past the year 9999.
+fn format_utc_millis(millis: u64) -> Option<String> {
+    let time = OffsetDateTime::from_unix_timestamp_nanos(i128::from(millis) * 1_000_000).ok()?;
+
+    time.format(format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+    ))
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use usergen::Color;
+
+    use super::*;
+    use crate::{import, TwitchUser};
+
+    fn event(name: &str, message: &str, elapsed: u64, badges: &str) -> RecordedEvent {
+        let timestamp = 1_699_122_612_000 + elapsed;
+
+        let irc = IrcMessage::new("PRIVMSG")
+            .tag("badges", badges)
+            .tag(
+                "emotes",
+                if message.starts_with("Kappa") {
+                    "25:0-4"
+                } else {
+                    ""
+                },
+            )
+            .tag("id", format!("id-{elapsed}"))
+            .tag("room-id", "4321")
+            .tag("tmi-sent-ts", timestamp.to_string())
+            .prefix(IrcMessage::user_prefix(&name.to_lowercase()))
+            .param("#fauxchat")
+            .param(message);
+
+        RecordedEvent {
+            timestamp,
+            elapsed,
+            channel: String::from("#fauxchat"),
+            message: message.to_string(),
+            user: TwitchUser {
+                color: Color::new(0x1E, 0x90, 0xFF),
+                is_mod: badges.contains("moderator"),
+                is_vip: badges.contains("vip"),
+                is_sub: badges.contains("subscriber"),
+                ..TwitchUser::new(name, format!("uid-{name}"))
+            },
+            irc: irc.to_string(),
+        }
+    }
+
+    fn events() -> Vec<RecordedEvent> {
+        vec![
+            event("SomeOne", "Kappa hi", 0, "moderator/1,subscriber/12"),
+            event("another", "Hello, \"world\"", 1_500, ""),
+            event("SomeOne", "bye", 10_000, "moderator/1,subscriber/12"),
+        ]
+    }
+
+    #[test]
+    fn test_srt() {
+        let srt = to_srt(&events(), &SubtitleOptions::default());
+
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,500\nSomeOne: Kappa hi\n\n\
+             2\n00:00:01,500 --> 00:00:06,500\nSomeOne: Kappa hi\nanother: Hello, \"world\"\n\n\
+             3\n00:00:10,000 --> 00:00:15,000\nSomeOne: bye\n\n"
+        );
+    }
+
+    #[test]
+    fn test_srt_line_limit() {
+        let options = SubtitleOptions {
+            lines: 1,
+            ..SubtitleOptions::default()
+        };
+
+        let srt = to_srt(&events(), &options);
+
+        assert!(srt.contains("00:00:01,500 --> 00:00:06,500\nanother: Hello, \"world\"\n\n"));
+    }
+
+    #[test]
+    fn test_ass() {
+        let ass = to_ass(&events(), &SubtitleOptions::default());
+
+        assert!(ass.starts_with("[Script Info]"));
+        assert!(ass.contains(
+            "Dialogue: 0,0:00:00.00,0:00:01.50,Chat,,0,0,0,,{\\b1\\c&HFF901E&}SomeOne{\\r}: Kappa hi\n"
+        ));
+        assert_eq!(ass.matches("Dialogue:").count(), 3);
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = to_csv(&events());
+
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                "timestamp,elapsed,user,message,badges",
+                "2023-11-04T18:30:12.000Z,0.000,SomeOne,Kappa hi,\"moderator/1,subscriber/12\"",
+                "2023-11-04T18:30:13.500Z,1.500,another,\"Hello, \"\"world\"\"\",",
+                "2023-11-04T18:30:22.000Z,10.000,SomeOne,bye,\"moderator/1,subscriber/12\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vod_json_round_trip() {
+        let events = events();
+
+        let json = to_vod_json(&events).unwrap();
+        let imported = import::from_vod_json(&json, "elsewhere").unwrap();
+
+        assert_eq!(imported.len(), events.len());
+
+        for (imported, event) in imported.iter().zip(&events) {
+            assert_eq!(imported.timestamp, event.timestamp);
+            assert_eq!(imported.elapsed, event.elapsed);
+            assert_eq!(imported.channel, event.channel);
+            assert_eq!(imported.user, event.user);
+            assert_eq!(badges(imported), badges(event));
+        }
+
+        let irc = imported[0].irc_message().unwrap();
+        assert_eq!(irc.tags.get("emotes"), Some("25:0-4"));
+        assert_eq!(irc.tags.get("room-id"), Some("4321"));
+    }
+
+    #[test]
+    fn test_format_utc_millis() {
+        assert_eq!(
+            format_utc_millis(0).as_deref(),
+            Some("1970-01-01T00:00:00.000Z")
+        );
+        assert_eq!(
+            format_utc_millis(1_699_122_612_345).as_deref(),
+            Some("2023-11-04T18:30:12.345Z")
+        );
+        assert_eq!(
+            import::parse_utc_millis(&format_utc_millis(951_782_400_000).unwrap()),
+            Some(951_782_400_000)
+        );
+        assert_eq!(format_utc_millis(u64::MAX), None);
+    }
+
+    #[test]
+    fn test_multiline_messages() {
+        let mut events = events();
+        events[0].message = String::from("first\n\nsecond\r\nthird");
+
+        let srt = to_srt(&events[..1], &SubtitleOptions::default());
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:05,000\nSomeOne: first second third\n\n"
+        );
+
+        let ass = to_ass(&events[..1], &SubtitleOptions::default());
+        let dialogue = ass.lines().last().unwrap();
+        assert!(dialogue.starts_with("Dialogue:") && dialogue.ends_with("first second third"));
+    }
+
+    #[test]
+    fn test_ass_escape() {
+        let escaped = ass_escape("C:\\New\\hello {\\b1}");
+
+        assert!(!escaped.contains("\\N") && !escaped.contains("\\h") && !escaped.contains("\\b"));
+        assert_eq!(escaped.replace('\u{2060}', ""), "C:\\New\\hello \\{\\b1\\}");
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("SRT".parse::<ExportFormat>().unwrap(), ExportFormat::Srt);
+        assert_eq!(
+            "vod".parse::<ExportFormat>().unwrap(),
+            ExportFormat::VodJson
+        );
+        assert!("txt".parse::<ExportFormat>().is_err());
+    }
+}
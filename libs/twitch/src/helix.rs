@@ -0,0 +1,561 @@
+//! The parts of the [Helix API](https://dev.twitch.tv/docs/api/reference) used to build the pool
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use reqwest::header::HeaderMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    auth::TwitchError, token::CLIENT, Follower, Pagination, SubscriberDatum, TwitchApiError,
+    VipDatum,
+};
+
+pub const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+/// The longest to wait for the rate limit to reset, in case the clock is off
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// One page of a paginated Helix response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    /// How many items there are across every page, for endpoints that count them
+    #[serde(default)]
+    pub total: Option<usize>,
+    #[serde(default)]
+    pub pagination: Pagination,
+}
+
+/// A Helix endpoint, with how many items it returns at once and the scope it needs
+struct Endpoint {
+    path: &'static str,
+    page_size: usize,
+    scope: Option<&'static str>,
+}
+
+const FOLLOWERS: Endpoint = Endpoint {
+    path: "channels/followers",
+    page_size: 100,
+    scope: Some("moderator:read:followers"),
+};
+
+const CHATTERS: Endpoint = Endpoint {
+    path: "chat/chatters",
+    page_size: 1000,
+    scope: Some("moderator:read:chatters"),
+};
+
+const VIPS: Endpoint = Endpoint {
+    path: "channels/vips",
+    page_size: 100,
+    scope: Some("channel:read:vips"),
+};
+
+const MODERATORS: Endpoint = Endpoint {
+    path: "moderation/moderators",
+    page_size: 100,
+    scope: Some("moderation:read"),
+};
+
+const SUBSCRIPTIONS: Endpoint = Endpoint {
+    path: "subscriptions",
+    page_size: 100,
+    scope: Some("channel:read:subscriptions"),
+};
+
+const USERS: Endpoint = Endpoint {
+    path: "users",
+    page_size: 100,
+    scope: None,
+};
+
+impl Endpoint {
+    fn missing_scope(&self, message: &str) -> TwitchApiError {
+        let message = match self.scope {
+            Some(scope) => format!(
+                "{message}. The token needs the {scope} scope, log in again with `fauxchat setup`"
+            ),
+            None => message.to_string(),
+        };
+
+        TwitchApiError::Auth {
+            endpoint: self.path.to_string(),
+            message,
+        }
+    }
+}
+
+/// How requests that fail for a reason that may pass are retried
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// How many times a request is tried again, after the first try
+    pub attempts: u32,
+    /// How long to wait before the first retry, which doubles for every one after
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelixUser {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+}
+
+#[async_trait]
+pub trait HelixApi: Send + Sync {
+    async fn followers(&self, broadcaster_id: &str) -> Result<Vec<Follower>, TwitchApiError>;
+
+    /// Everyone currently in chat, as seen by a moderator of the channel
+    async fn chatters(
+        &self,
+        broadcaster_id: &str,
+        moderator_id: &str,
+    ) -> Result<Vec<VipDatum>, TwitchApiError>;
+
+    async fn vips(&self, broadcaster_id: &str) -> Result<Vec<VipDatum>, TwitchApiError>;
+
+    async fn moderators(&self, broadcaster_id: &str) -> Result<Vec<VipDatum>, TwitchApiError>;
+
+    async fn subscribers(
+        &self,
+        broadcaster_id: &str,
+    ) -> Result<Vec<SubscriberDatum>, TwitchApiError>;
+
+    /// Looks up users by their id
+    async fn users(&self, ids: &[String]) -> Result<Vec<HelixUser>, TwitchApiError>;
+}
+
+/// Helix itself, or anything answering like it such as [`crate::mock`]
+#[derive(Debug, Clone)]
+pub struct Helix {
+    base_url: String,
+    retry: Retry,
+    /// When the rate limit resets, once every request until then has been used
+    rate_limited_until: Arc<Mutex<Option<SystemTime>>>,
+}
+
+impl Default for Helix {
+    /// Uses `FAUXCHAT_HELIX_URL` if it is set, otherwise Twitch
+    fn default() -> Self {
+        Self::new(std::env::var("FAUXCHAT_HELIX_URL").unwrap_or_else(|_| HELIX_URL.to_string()))
+    }
+}
+
+impl Helix {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            retry: Retry::default(),
+            rate_limited_until: Arc::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Gets a page, retrying if it fails for a reason that may pass
+    async fn get<T: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        query: &str,
+    ) -> Result<Page<T>, TwitchApiError> {
+        let mut attempt = 0;
+
+        loop {
+            if let Some(wait) = self.rate_limit_wait() {
+                tracing::info!("Waiting {wait:?} for the Twitch rate limit to reset");
+                tokio::time::sleep(wait).await;
+            }
+
+            match self.try_get(endpoint, query).await {
+                Err(e) if e.is_transient() && attempt < self.retry.attempts => {
+                    let wait = match e {
+                        TwitchApiError::RateLimited { retry_after, .. } => retry_after,
+                        _ => self.retry.backoff * 2u32.pow(attempt),
+                    };
+
+                    tracing::warn!("{e}, trying again in {wait:?}");
+                    tokio::time::sleep(wait).await;
+
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_get<T: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        query: &str,
+    ) -> Result<Page<T>, TwitchApiError> {
+        let url = format!("{}/{}?{query}", self.base_url, endpoint.path);
+
+        let resp = CLIENT.get(&url).await?;
+        let status = resp.status();
+
+        self.track_rate_limit(resp.headers());
+
+        let text = resp.text().await?;
+
+        if status.is_success() {
+            return serde_json::from_str(&text).map_err(|source| TwitchApiError::Decode {
+                endpoint: endpoint.path.to_string(),
+                source,
+            });
+        }
+
+        let message = serde_json::from_str::<TwitchError>(&text)
+            .map(|error| error.message)
+            .unwrap_or(text);
+
+        Err(match status.as_u16() {
+            401 | 403 => endpoint.missing_scope(&message),
+            404 => TwitchApiError::NotFound {
+                endpoint: endpoint.path.to_string(),
+                message,
+            },
+            429 => TwitchApiError::RateLimited {
+                endpoint: endpoint.path.to_string(),
+                retry_after: self.rate_limit_wait().unwrap_or(self.retry.backoff),
+            },
+            status => TwitchApiError::Status {
+                endpoint: endpoint.path.to_string(),
+                status,
+                message,
+            },
+        })
+    }
+
+    /// Remembers when the rate limit resets once there are no requests left
+    fn track_rate_limit(&self, headers: &HeaderMap) {
+        let header = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+
+        if header("Ratelimit-Remaining") == Some(0) {
+            *self.rate_limited_until.lock() = header("Ratelimit-Reset")
+                .map(|reset| SystemTime::UNIX_EPOCH + Duration::from_secs(reset));
+        }
+    }
+
+    /// How long until requests can be made again, if the rate limit has been reached
+    fn rate_limit_wait(&self) -> Option<Duration> {
+        let until = (*self.rate_limited_until.lock())?;
+
+        until
+            .duration_since(SystemTime::now())
+            .ok()
+            .map(|wait| wait.min(MAX_RATE_LIMIT_WAIT))
+    }
+
+    /// Gets every page of a paginated endpoint
+    async fn get_all<T: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        query: &str,
+    ) -> Result<Vec<T>, TwitchApiError> {
+        let query = format!("{query}&first={}", endpoint.page_size);
+
+        let mut page: Page<T> = self.get(endpoint, &query).await?;
+
+        // Without the scope some endpoints only say how many items there are
+        if page.data.is_empty() && page.total.is_some_and(|total| total > 0) {
+            return Err(endpoint.missing_scope("Only the total was returned"));
+        }
+
+        let mut data = std::mem::take(&mut page.data);
+
+        while let Some(cursor) = page.pagination.cursor.take() {
+            page = self
+                .get(endpoint, &format!("{query}&after={cursor}"))
+                .await?;
+            data.append(&mut page.data);
+        }
+
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl HelixApi for Helix {
+    async fn followers(&self, broadcaster_id: &str) -> Result<Vec<Follower>, TwitchApiError> {
+        self.get_all(&FOLLOWERS, &format!("broadcaster_id={broadcaster_id}"))
+            .await
+    }
+
+    async fn chatters(
+        &self,
+        broadcaster_id: &str,
+        moderator_id: &str,
+    ) -> Result<Vec<VipDatum>, TwitchApiError> {
+        self.get_all(
+            &CHATTERS,
+            &format!("broadcaster_id={broadcaster_id}&moderator_id={moderator_id}"),
+        )
+        .await
+    }
+
+    async fn vips(&self, broadcaster_id: &str) -> Result<Vec<VipDatum>, TwitchApiError> {
+        self.get_all(&VIPS, &format!("broadcaster_id={broadcaster_id}"))
+            .await
+    }
+
+    async fn moderators(&self, broadcaster_id: &str) -> Result<Vec<VipDatum>, TwitchApiError> {
+        self.get_all(&MODERATORS, &format!("broadcaster_id={broadcaster_id}"))
+            .await
+    }
+
+    async fn subscribers(
+        &self,
+        broadcaster_id: &str,
+    ) -> Result<Vec<SubscriberDatum>, TwitchApiError> {
+        self.get_all(&SUBSCRIPTIONS, &format!("broadcaster_id={broadcaster_id}"))
+            .await
+    }
+
+    async fn users(&self, ids: &[String]) -> Result<Vec<HelixUser>, TwitchApiError> {
+        let mut users = Vec::with_capacity(ids.len());
+
+        for chunk in ids.chunks(USERS.page_size) {
+            let query = chunk
+                .iter()
+                .map(|id| format!("id={id}"))
+                .collect::<Vec<_>>()
+                .join("&");
+
+            let mut page: Page<HelixUser> = self.get(&USERS, &query).await?;
+            users.append(&mut page.data);
+        }
+
+        Ok(users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        mock::{self, Fixtures},
+        PoolSource, SubTier, UserPool,
+    };
+
+    async fn helix() -> Helix {
+        // Small pages so every list takes a few requests
+        let base_url = mock::serve_helix(Fixtures {
+            page_size: 2,
+            ..Fixtures::default()
+        })
+        .await
+        .unwrap();
+
+        Helix::new(format!("{base_url}/helix"))
+    }
+
+    #[tokio::test]
+    async fn test_pagination() {
+        let helix = helix().await;
+
+        let followers = helix.followers(mock::BROADCASTER_ID).await.unwrap();
+
+        assert_eq!(followers.len(), 7);
+        assert_eq!(followers[0].user_login, "sleepy_otter");
+        assert_eq!(followers[6].user_login, "chattychad");
+
+        assert!(helix.followers("1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_pool() {
+        let pool = UserPool::download(&helix().await, mock::BROADCASTER_ID, PoolSource::Followers)
+            .await
+            .unwrap();
+
+        let user = |name: &str| {
+            let user = pool.users.iter().find(|user| user.name == name).unwrap();
+            (user.is_mod, user.is_vip, user.is_sub)
+        };
+
+        assert_eq!(pool.users.len(), 8);
+        assert_eq!(pool.users[0].uid, mock::BROADCASTER_ID);
+        assert_eq!(user("FauxChat"), (false, false, false));
+        assert_eq!(user("ModMaggie"), (true, false, true));
+        assert_eq!(user("PixelPanda"), (false, true, true));
+        assert_eq!(user("VIP_Vinny"), (false, true, false));
+        assert_eq!(user("SubSarah"), (false, false, true));
+        assert_eq!(user("LurkingLarry"), (false, false, false));
+
+        let tier = |name: &str| {
+            let user = pool.users.iter().find(|user| user.name == name).unwrap();
+            user.subscription.tier
+        };
+
+        assert_eq!(tier("SubSarah"), SubTier::Two);
+        assert_eq!(tier("ModMaggie"), SubTier::One);
+    }
+
+    #[tokio::test]
+    async fn test_download_pool_from_chatters() {
+        let pool = UserPool::download(&helix().await, mock::BROADCASTER_ID, PoolSource::Chatters)
+            .await
+            .unwrap();
+
+        let names: Vec<_> = pool.users.iter().map(|user| user.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            [
+                "FauxChat",
+                "PixelPanda",
+                "ModMaggie",
+                "SubSarah",
+                "ChattyChad",
+                "Random_Raider"
+            ]
+        );
+        assert!(pool.users[2].is_mod);
+    }
+
+    #[tokio::test]
+    async fn test_missing_scope() {
+        // Without the scope, Twitch only says how many followers there are
+        let base_url =
+            mock::serve(|_| (200, r#"{"total":7,"data":[],"pagination":{}}"#.to_string()))
+                .await
+                .unwrap();
+
+        let error = Helix::new(base_url)
+            .followers(mock::BROADCASTER_ID)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&error, TwitchApiError::Auth { message, .. } if message.contains("moderator:read:followers"))
+        );
+    }
+
+    fn fast_retry() -> Retry {
+        Retry {
+            attempts: 2,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+
+        let base_url = mock::serve(move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                mock::twitch_error(503, "Service Unavailable")
+            } else {
+                (200, r#"{"data":[]}"#.to_string())
+            }
+        })
+        .await
+        .unwrap();
+
+        let helix = Helix::new(base_url).with_retry(fast_retry());
+
+        assert!(helix.vips(mock::BROADCASTER_ID).await.unwrap().is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+
+        let base_url = mock::serve(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            mock::twitch_error(503, "Service Unavailable")
+        })
+        .await
+        .unwrap();
+
+        let helix = Helix::new(base_url).with_retry(fast_retry());
+
+        assert!(matches!(
+            helix.vips(mock::BROADCASTER_ID).await,
+            Err(TwitchApiError::Status { status: 503, .. })
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let base_url = mock::serve(|req| match req.route() {
+            "/channels/vips" => (200, String::from("<html>")),
+            _ => mock::twitch_error(404, "Not Found"),
+        })
+        .await
+        .unwrap();
+
+        let helix = Helix::new(base_url).with_retry(fast_retry());
+
+        assert!(matches!(
+            helix.vips(mock::BROADCASTER_ID).await,
+            Err(TwitchApiError::Decode { .. })
+        ));
+        assert!(matches!(
+            helix.moderators(mock::BROADCASTER_ID).await,
+            Err(TwitchApiError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_rate_limit() {
+        let reset = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 2;
+
+        // The first page uses up the rate limit, so the second has to wait for it to reset
+        let base_url = mock::serve(move |req| {
+            if req.query("after").is_empty() {
+                mock::Response::from((
+                    200,
+                    r#"{"data":[{"user_id":"1","user_login":"a","user_name":"A","followed_at":""}],"pagination":{"cursor":"1"}}"#.to_string(),
+                ))
+                .with_header("Ratelimit-Remaining", &0)
+                .with_header("Ratelimit-Reset", &reset)
+            } else {
+                mock::Response::from((
+                    200,
+                    r#"{"data":[{"user_id":"2","user_login":"b","user_name":"B","followed_at":""}],"pagination":{}}"#.to_string(),
+                ))
+                .with_header("Ratelimit-Remaining", &799)
+            }
+        })
+        .await
+        .unwrap();
+
+        let start = std::time::Instant::now();
+        let followers = Helix::new(base_url)
+            .followers(mock::BROADCASTER_ID)
+            .await
+            .unwrap();
+
+        assert_eq!(followers.len(), 2);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+}
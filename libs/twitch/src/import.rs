@@ -0,0 +1,469 @@
+//! Importers turning real Twitch chat logs into recordings, so that past streams can be replayed
+//!
+//! Supported are raw IRC logs, as saved by Chatterino and other loggers, and the JSON written by VOD chat downloaders.
+//! Pools of users are imported by [`pool`].
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use usergen::Color;
+
+use crate::{
+    irc::{self, IrcMessage, ParseError},
+    recording::RecordedEvent,
+    SubTier, Subscription, TwitchUser,
+};
+
+pub mod pool;
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("Invalid IRC message on line {line}: {source}")]
+    InvalidLine { line: usize, source: ParseError },
+    #[error("Invalid chat download: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("The log does not contain any chat messages")]
+    Empty,
+}
+
+/// Reads the user who sent a `PRIVMSG` from its tags, as Twitch sends them
+#[must_use]
+pub fn user_from_privmsg(msg: &IrcMessage) -> TwitchUser {
+    let tag = |key| msg.tags.get(key).filter(|value| !value.is_empty());
+
+    let login = msg
+        .prefix
+        .as_deref()
+        .and_then(|prefix| prefix.split('!').next())
+        .unwrap_or_default();
+
+    let badges = tag("badges").unwrap_or_default();
+    let has_badge = |name: &str| {
+        badges
+            .split(',')
+            .any(|badge| badge.split('/').next() == Some(name))
+    };
+
+    // The badge version gives the tier, while the badge info has the exact months
+    let version = |name: &str| {
+        badges.split(',').find_map(|badge| {
+            let (badge, version) = badge.split_once('/')?;
+            (badge == name).then(|| version.parse::<u32>().ok())?
+        })
+    };
+
+    let months = tag("badge-info")
+        .unwrap_or_default()
+        .split(',')
+        .find_map(|info| info.strip_prefix("subscriber/")?.parse().ok());
+
+    let subscription = Subscription {
+        tier: match version("subscriber").or_else(|| version("founder")) {
+            Some(3000..) => SubTier::Three,
+            Some(2000..) => SubTier::Two,
+            _ => SubTier::One,
+        },
+        months: months.unwrap_or(1),
+    };
+
+    TwitchUser {
+        name: tag("display-name").unwrap_or(login).to_string(),
+        uid: tag("user-id").unwrap_or("fake_uid").to_string(),
+        color: tag("color")
+            .and_then(|color| color.parse().ok())
+            .unwrap_or_else(Color::generate_light),
+        is_mod: tag("mod") == Some("1") || has_badge("moderator"),
+        is_vip: has_badge("vip"),
+        is_sub: tag("subscriber") == Some("1") || has_badge("subscriber") || has_badge("founder"),
+        subscription,
+    }
+}
+
+/// Turns the messages into events, timed relative to the first message
+fn to_events(messages: Vec<(u64, IrcMessage)>) -> Result<Vec<RecordedEvent>, ImportError> {
+    let first = messages.first().ok_or(ImportError::Empty)?.0;
+
+    Ok(messages
+        .into_iter()
+        .map(|(timestamp, msg)| RecordedEvent {
+            timestamp,
+            elapsed: timestamp.saturating_sub(first),
+            channel: msg.params.first().cloned().unwrap_or_default(),
+            message: msg.params.last().cloned().unwrap_or_default(),
+            user: user_from_privmsg(&msg),
+            irc: msg.to_string(),
+        })
+        .collect())
+}
+
+/// Imports a raw IRC log, with one message per line
+///
+/// Lines may start with a `[timestamp]` added by the logger, which is ignored in favour of the `tmi-sent-ts` tag.
+/// Anything that is not a chat message, such as joins or notices, is skipped.
+pub fn from_irc_log(log: &str) -> Result<Vec<RecordedEvent>, ImportError> {
+    let mut messages = Vec::new();
+
+    for (i, line) in log.lines().enumerate() {
+        let line = line.trim();
+
+        // Skip past any timestamp the logger added
+        let line = match line.strip_prefix('[') {
+            Some(rest) => rest
+                .split_once(']')
+                .map_or(line, |(_, rest)| rest.trim_start()),
+            None => line,
+        };
+
+        // Along with lines that are not IRC at all
+        if !line.starts_with(['@', ':']) {
+            continue;
+        }
+
+        let msg: IrcMessage = line.parse().map_err(|source| ImportError::InvalidLine {
+            line: i + 1,
+            source,
+        })?;
+
+        if msg.command != "PRIVMSG" {
+            continue;
+        }
+
+        let timestamp: Option<u64> = msg.tags.get("tmi-sent-ts").and_then(|ts| ts.parse().ok());
+
+        messages.push((timestamp, msg));
+    }
+
+    // Messages without a timestamp are treated as being sent alongside the previous one,
+    // or the first with a timestamp if none came before them
+    let mut last_timestamp = messages
+        .iter()
+        .find_map(|(timestamp, _)| *timestamp)
+        .unwrap_or_default();
+
+    to_events(
+        messages
+            .into_iter()
+            .map(|(timestamp, msg)| {
+                last_timestamp = timestamp.unwrap_or(last_timestamp);
+                (last_timestamp, msg)
+            })
+            .collect(),
+    )
+}
+
+/// The chat download written by `TwitchDownloader`, keeping only what is needed
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ChatDownload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) streamer: Option<Streamer>,
+    pub(crate) comments: Vec<Comment>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Streamer {
+    pub(crate) name: String,
+    pub(crate) id: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Comment {
+    #[serde(rename = "_id")]
+    pub(crate) id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<String>,
+    pub(crate) content_offset_seconds: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) channel_id: Option<String>,
+    pub(crate) commenter: Commenter,
+    pub(crate) message: CommentMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Commenter {
+    #[serde(rename = "_id")]
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CommentMessage {
+    pub(crate) body: String,
+    #[serde(default)]
+    pub(crate) user_color: Option<String>,
+    #[serde(default)]
+    pub(crate) user_badges: Vec<CommentBadge>,
+    #[serde(default)]
+    pub(crate) emoticons: Vec<Emoticon>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CommentBadge {
+    #[serde(rename = "_id")]
+    pub(crate) id: String,
+    pub(crate) version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Emoticon {
+    #[serde(rename = "_id")]
+    pub(crate) id: String,
+    pub(crate) begin: usize,
+    pub(crate) end: usize,
+}
+
+/// Formats emotes the way the `emotes` tag expects, i.e. `25:0-4,6-10/1902:12-16`
+fn emotes_tag(emoticons: &[Emoticon]) -> String {
+    let mut by_id: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+
+    for emote in emoticons {
+        by_id
+            .entry(&emote.id)
+            .or_default()
+            .push(format!("{}-{}", emote.begin, emote.end));
+    }
+
+    by_id
+        .into_iter()
+        .map(|(id, ranges)| format!("{id}:{}", ranges.join(",")))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Converts an RFC 3339 time, such as `2023-11-04T18:30:12.345Z`, into unix milliseconds
+pub(crate) fn parse_utc_millis(time: &str) -> Option<u64> {
+    let time = OffsetDateTime::parse(time, &Rfc3339).ok()?;
+
+    u64::try_from(time.unix_timestamp_nanos() / 1_000_000).ok()
+}
+
+/// Imports the JSON written by VOD chat downloaders, such as `TwitchDownloader`
+///
+/// The channel is taken from the download when it is known, falling back to the one given.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn from_vod_json(json: &str, channel: &str) -> Result<Vec<RecordedEvent>, ImportError> {
+    let download: ChatDownload = serde_json::from_str(json)?;
+
+    let channel = download
+        .streamer
+        .as_ref()
+        .map_or(channel, |streamer| streamer.name.as_str());
+
+    let room_id = download
+        .streamer
+        .as_ref()
+        .map(|streamer| match &streamer.id {
+            serde_json::Value::String(id) => id.clone(),
+            id => id.to_string(),
+        });
+
+    let start = download
+        .comments
+        .first()
+        .and_then(|comment| comment.created_at.as_deref())
+        .and_then(parse_utc_millis)
+        .map(|created_at| {
+            let offset = download.comments[0].content_offset_seconds * 1_000.0;
+
+            created_at.saturating_sub(offset.max(0.0) as u64)
+        })
+        .unwrap_or_default();
+
+    let mut comments = download.comments;
+    comments.sort_by(|a, b| {
+        a.content_offset_seconds
+            .total_cmp(&b.content_offset_seconds)
+    });
+
+    let messages = comments
+        .into_iter()
+        .map(|comment| {
+            let timestamp = start + (comment.content_offset_seconds.max(0.0) * 1_000.0) as u64;
+
+            let badges = comment
+                .message
+                .user_badges
+                .iter()
+                .map(|badge| format!("{}/{}", badge.id, badge.version))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let has_badge = |name: &str| {
+                comment
+                    .message
+                    .user_badges
+                    .iter()
+                    .any(|badge| badge.id == name)
+            };
+
+            let msg = IrcMessage::new("PRIVMSG")
+                .tag("badges", badges)
+                .tag(
+                    "color",
+                    comment.message.user_color.clone().unwrap_or_default(),
+                )
+                .tag("display-name", &comment.commenter.display_name)
+                .tag("emotes", emotes_tag(&comment.message.emoticons))
+                .tag("id", &comment.id)
+                .tag("mod", if has_badge("moderator") { "1" } else { "0" })
+                .tag(
+                    "room-id",
+                    comment
+                        .channel_id
+                        .clone()
+                        .or_else(|| room_id.clone())
+                        .unwrap_or_default(),
+                )
+                .tag(
+                    "subscriber",
+                    if has_badge("subscriber") || has_badge("founder") {
+                        "1"
+                    } else {
+                        "0"
+                    },
+                )
+                .tag("tmi-sent-ts", timestamp.to_string())
+                .tag("user-id", &comment.commenter.id)
+                .prefix(IrcMessage::user_prefix(&comment.commenter.name))
+                .param(irc::channel_name(channel))
+                .param(comment.message.body);
+
+            (timestamp, msg)
+        })
+        .collect();
+
+    to_events(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IRC_LOG: &str = r"# Start logging at 2023-11-04 18:30:00
+[18:30:12] @badge-info=subscriber/14;badges=moderator/1,subscriber/12;color=#1E90FF;display-name=SomeOne;emotes=25:6-10;id=a1;mod=1;subscriber=1;tmi-sent-ts=1699122612345;user-id=1234 :someone!someone@someone.tmi.twitch.tv PRIVMSG #fauxchat :Hello Kappa
+[18:30:13] :tmi.twitch.tv 353 justinfan #fauxchat :someone
+@badges=vip/1;color=;display-name=another;id=a2;mod=0;subscriber=0;tmi-sent-ts=1699122614845;user-id=5678 :another!another@another.tmi.twitch.tv PRIVMSG #fauxchat :hey there
+";
+
+    #[test]
+    fn test_irc_log() {
+        let events = from_irc_log(IRC_LOG).unwrap();
+
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(events[0].elapsed, 0);
+        assert_eq!(events[0].timestamp, 1_699_122_612_345);
+        assert_eq!(events[0].message, "Hello Kappa");
+        assert_eq!(events[0].user.name, "SomeOne");
+        assert_eq!(events[0].user.uid, "1234");
+        assert_eq!(events[0].user.color, Color::new(0x1E, 0x90, 0xFF));
+        assert!(events[0].user.is_mod && events[0].user.is_sub && !events[0].user.is_vip);
+        assert_eq!(
+            events[0].user.subscription,
+            Subscription {
+                tier: SubTier::One,
+                months: 14,
+            }
+        );
+
+        // Badges and emotes are kept exactly as they were
+        let irc = events[0].irc_message().unwrap();
+        assert_eq!(irc.tags.get("badges"), Some("moderator/1,subscriber/12"));
+        assert_eq!(irc.tags.get("emotes"), Some("25:6-10"));
+
+        assert_eq!(events[1].elapsed, 2_500);
+        assert_eq!(events[1].user.name, "another");
+        assert!(events[1].user.is_vip && !events[1].user.is_sub);
+    }
+
+    #[test]
+    fn test_missing_timestamps() {
+        let log = ":someone!someone@someone.tmi.twitch.tv PRIVMSG #fauxchat :first\n\
+                   @tmi-sent-ts=1699122612345 :another!another@another.tmi.twitch.tv PRIVMSG #fauxchat :second\n\
+                   :someone!someone@someone.tmi.twitch.tv PRIVMSG #fauxchat :third\n\
+                   @tmi-sent-ts=1699122613345 :another!another@another.tmi.twitch.tv PRIVMSG #fauxchat :fourth";
+
+        let events = from_irc_log(log).unwrap();
+        let elapsed: Vec<_> = events.iter().map(|event| event.elapsed).collect();
+
+        assert_eq!(elapsed, [0, 0, 0, 1_000]);
+        assert_eq!(events[0].timestamp, 1_699_122_612_345);
+    }
+
+    #[test]
+    fn test_non_ascii_message() {
+        let log = "@display-name=Ünïcödé;tmi-sent-ts=1 :unicode!unicode@unicode.tmi.twitch.tv PRIVMSG #fauxchat :héllo 👩\u{200d}💻 e\u{301}";
+
+        let events = from_irc_log(log).unwrap();
+
+        assert_eq!(events[0].message, "héllo 👩\u{200d}💻 e\u{301}");
+        assert_eq!(events[0].user.name, "Ünïcödé");
+    }
+
+    #[test]
+    fn test_empty_log() {
+        assert!(matches!(
+            from_irc_log("# Nothing here\n"),
+            Err(ImportError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_vod_json() {
+        let json = r##"{
+            "streamer": { "name": "fauxchat", "id": 4321 },
+            "comments": [
+                {
+                    "_id": "b2",
+                    "created_at": "2023-11-04T18:30:14.500Z",
+                    "content_offset_seconds": 14.5,
+                    "commenter": { "_id": "5678", "name": "another", "display_name": "Another" },
+                    "message": { "body": "LUL same", "user_color": null, "user_badges": [{ "_id": "vip", "version": "1" }] }
+                },
+                {
+                    "_id": "b1",
+                    "created_at": "2023-11-04T18:30:12Z",
+                    "content_offset_seconds": 12,
+                    "commenter": { "_id": "1234", "name": "someone", "display_name": "SomeOne" },
+                    "message": {
+                        "body": "Kappa hi Kappa",
+                        "user_color": "#1E90FF",
+                        "user_badges": [{ "_id": "subscriber", "version": "12" }],
+                        "emoticons": [{ "_id": "25", "begin": 0, "end": 4 }, { "_id": "25", "begin": 9, "end": 13 }]
+                    }
+                }
+            ]
+        }"##;
+
+        let events = from_vod_json(json, "elsewhere").unwrap();
+
+        assert_eq!(events.len(), 2);
+
+        // Sorted by offset, not by order in the file
+        assert_eq!(events[0].message, "Kappa hi Kappa");
+        assert_eq!(events[0].timestamp, 1_699_122_612_000);
+        assert_eq!(events[0].channel, "#fauxchat");
+        assert_eq!(events[0].user.color, Color::new(0x1E, 0x90, 0xFF));
+        assert!(events[0].user.is_sub);
+
+        let irc = events[0].irc_message().unwrap();
+        assert_eq!(irc.tags.get("emotes"), Some("25:0-4,9-13"));
+        assert_eq!(irc.tags.get("room-id"), Some("4321"));
+
+        assert_eq!(events[1].elapsed, 2_500);
+        assert_eq!(events[1].user.name, "Another");
+        assert!(events[1].user.is_vip);
+    }
+
+    #[test]
+    fn test_parse_utc_millis() {
+        assert_eq!(parse_utc_millis("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_utc_millis("2023-11-04T18:30:12.345Z"),
+            Some(1_699_122_612_345)
+        );
+        assert_eq!(parse_utc_millis("2023-11-04 18:30:12"), None);
+    }
+}
@@ -0,0 +1,506 @@
+//! Importers building a pool from CSV, lists of usernames and other pool files
+//!
+//! Usernames breaking Twitch's login rules, and entries sharing a name or uid with someone already imported, are left
+//! out and reported as [`PoolIssue`]s instead of failing the whole import. Pool files are not held to the login rules,
+//! as they hold display names, which may be written in any script.
+
+use std::{collections::HashSet, path::Path};
+
+use thiserror::Error;
+
+use crate::{SubTier, Subscription, TwitchUser, UserPool};
+
+/// Where uids handed out to imported users without one start from
+const FIRST_IMPORTED_UID: u64 = 300_000_000;
+
+#[derive(Debug, Error)]
+pub enum PoolImportError {
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("{path} is not a valid pool file: {source}")]
+    InvalidPool {
+        path: String,
+        source: serde_json::Error,
+    },
+}
+
+/// Why a name can't be a Twitch login
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LoginError {
+    #[error("is shorter than 4 characters")]
+    TooShort,
+    #[error("is longer than 25 characters")]
+    TooLong,
+    #[error("starts with an underscore")]
+    LeadingUnderscore,
+    #[error("contains {0:?}, only letters, digits and underscores are allowed")]
+    InvalidChar(char),
+}
+
+/// Checks the name follows Twitch's rules for logins, where display names may only differ in capitalisation
+pub fn validate_login(name: &str) -> Result<(), LoginError> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '_')
+    {
+        return Err(LoginError::InvalidChar(c));
+    }
+
+    match name.len() {
+        ..=3 => Err(LoginError::TooShort),
+        26.. => Err(LoginError::TooLong),
+        _ if name.starts_with('_') => Err(LoginError::LeadingUnderscore),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Problem {
+    #[error("{0} {1}")]
+    InvalidLogin(String, LoginError),
+    #[error("{0} is already in the pool")]
+    DuplicateName(String),
+    #[error("The uid {0} is already in the pool")]
+    DuplicateUid(String),
+    #[error("Invalid color {0:?}, expected #RRGGBB")]
+    InvalidColor(String),
+    #[error("Unknown role {0:?}, expected mod, vip, sub, tier1, tier2 or tier3")]
+    UnknownRole(String),
+}
+
+/// An entry that was left out of the pool, or imported differently than it was written
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{origin}: {problem}")]
+pub struct PoolIssue {
+    /// The file and line, or index in a pool file, the entry came from
+    pub origin: String,
+    pub problem: Problem,
+}
+
+/// The kinds of files a pool can be imported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolFormat {
+    /// A `pool.json`, as downloaded or saved by the app
+    Json,
+    /// Rows of `name,color,roles,uid`, where everything but the name may be left empty
+    Csv,
+    /// A username on each line
+    Usernames,
+}
+
+impl PoolFormat {
+    /// Guesses the format from the extension, where anything unknown is a list of usernames
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::Csv,
+            _ => Self::Usernames,
+        }
+    }
+}
+
+/// Collects users from any number of sources into one pool
+#[derive(Debug, Default)]
+pub struct PoolImport {
+    users: Vec<TwitchUser>,
+    /// Where each user came from, empty for those already in the pool
+    origins: Vec<String>,
+    names: HashSet<String>,
+    uids: HashSet<String>,
+    /// Imported users who still need a uid
+    missing_uids: Vec<usize>,
+    issues: Vec<PoolIssue>,
+}
+
+impl PoolImport {
+    /// Starts from an existing pool, whose users are kept as they are even if they break the rules
+    #[must_use]
+    pub fn from_pool(pool: UserPool) -> Self {
+        let mut import = Self::default();
+
+        for user in pool.users {
+            import.names.insert(user.name.to_lowercase());
+            import.uids.insert(user.uid.clone());
+            import.users.push(user);
+            import.origins.push(String::new());
+        }
+
+        import
+    }
+
+    fn flag(&mut self, origin: &str, problem: Problem) {
+        self.issues.push(PoolIssue {
+            origin: origin.to_string(),
+            problem,
+        });
+    }
+
+    /// Adds the user, unless they are already in the pool
+    fn push(&mut self, origin: &str, user: TwitchUser, has_uid: bool) {
+        let name = user.name.to_lowercase();
+
+        if self.names.contains(&name) {
+            return self.flag(origin, Problem::DuplicateName(user.name));
+        }
+
+        if has_uid && self.uids.contains(&user.uid) {
+            return self.flag(origin, Problem::DuplicateUid(user.uid));
+        }
+
+        self.names.insert(name);
+
+        if has_uid {
+            self.uids.insert(user.uid.clone());
+        } else {
+            self.missing_uids.push(self.users.len());
+        }
+
+        self.users.push(user);
+        self.origins.push(origin.to_string());
+    }
+
+    /// Adds the user named by their login, unless it breaks Twitch's rules or they are already in the pool
+    fn push_login(&mut self, origin: &str, user: TwitchUser, has_uid: bool) {
+        if let Err(e) = validate_login(&user.name) {
+            return self.flag(origin, Problem::InvalidLogin(user.name, e));
+        }
+
+        self.push(origin, user, has_uid);
+    }
+
+    /// Imports the file in the format its extension suggests
+    pub fn file(&mut self, path: &Path) -> Result<&mut Self, PoolImportError> {
+        let origin = path.display().to_string();
+
+        let contents = std::fs::read_to_string(path).map_err(|source| PoolImportError::Io {
+            path: origin.clone(),
+            source,
+        })?;
+
+        match PoolFormat::from_path(path) {
+            PoolFormat::Json => {
+                let pool = serde_json::from_str(&contents).map_err(|source| {
+                    PoolImportError::InvalidPool {
+                        path: origin.clone(),
+                        source,
+                    }
+                })?;
+
+                Ok(self.pool(&origin, pool))
+            }
+            PoolFormat::Csv => Ok(self.csv(&origin, &contents)),
+            PoolFormat::Usernames => Ok(self.usernames(&origin, &contents)),
+        }
+    }
+
+    /// Merges in another pool, where whoever was imported first is kept
+    pub fn pool(&mut self, origin: &str, pool: UserPool) -> &mut Self {
+        for (i, user) in pool.users.into_iter().enumerate() {
+            self.push(&format!("{origin}#{i}"), user, true);
+        }
+
+        self
+    }
+
+    /// Imports a username from each line, ignoring blank lines and `#` comments
+    pub fn usernames(&mut self, origin: &str, list: &str) -> &mut Self {
+        for (i, line) in list.lines().enumerate() {
+            let name = line.trim();
+
+            if name.is_empty() || name.starts_with('#') {
+                continue;
+            }
+
+            self.push_login(
+                &format!("{origin}:{}", i + 1),
+                TwitchUser::new(name, String::new()),
+                false,
+            );
+        }
+
+        self
+    }
+
+    /// Imports rows of `name,color,roles,uid`, along with a header naming the columns if they are in another order
+    ///
+    /// Roles are separated by spaces, `|` or `;`, and an empty color is generated.
+    pub fn csv(&mut self, origin: &str, csv: &str) -> &mut Self {
+        let mut columns = ["name", "color", "roles", "uid"].map(String::from).to_vec();
+
+        for (i, line) in csv.lines().enumerate() {
+            let origin = format!("{origin}:{}", i + 1);
+            let fields = split_csv(line);
+
+            if fields.iter().all(String::is_empty) {
+                continue;
+            }
+
+            if i == 0
+                && fields
+                    .iter()
+                    .any(|field| field.eq_ignore_ascii_case("name"))
+            {
+                columns = fields.iter().map(|field| field.to_lowercase()).collect();
+                continue;
+            }
+
+            let field = |column: &str| {
+                columns
+                    .iter()
+                    .position(|name| name == column)
+                    .and_then(|i| fields.get(i))
+                    .map(String::as_str)
+                    .filter(|field| !field.is_empty())
+            };
+
+            let mut user = TwitchUser::new(field("name").unwrap_or_default(), String::new());
+
+            if let Some(color) = field("color") {
+                match color.parse() {
+                    Ok(color) => user.color = color,
+                    Err(_) => self.flag(&origin, Problem::InvalidColor(color.to_string())),
+                }
+            }
+
+            for role in field("roles")
+                .unwrap_or_default()
+                .split([' ', '|', ';'])
+                .filter(|role| !role.is_empty())
+            {
+                if let Err(problem) = give_role(&mut user, role) {
+                    self.flag(&origin, problem);
+                }
+            }
+
+            let uid = field("uid");
+
+            if let Some(uid) = uid {
+                user.uid = uid.to_string();
+            }
+
+            self.push_login(&origin, user, uid.is_some());
+        }
+
+        self
+    }
+
+    /// Adds everyone imported into `other` who isn't already here, along with the issues found importing them
+    ///
+    /// Files can then be read into an import of their own before the pool they go into is locked.
+    pub fn merge(&mut self, other: PoolImport) -> &mut Self {
+        let missing_uids: HashSet<usize> = other.missing_uids.into_iter().collect();
+
+        self.issues.extend(other.issues);
+
+        for (i, (user, origin)) in other.users.into_iter().zip(other.origins).enumerate() {
+            self.push(&origin, user, !missing_uids.contains(&i));
+        }
+
+        self
+    }
+
+    /// The imported pool, where users without a uid are given one nobody else has
+    #[must_use]
+    pub fn finish(mut self) -> (UserPool, Vec<PoolIssue>) {
+        let mut next = FIRST_IMPORTED_UID;
+
+        for i in std::mem::take(&mut self.missing_uids) {
+            while self.uids.contains(&next.to_string()) {
+                next += 1;
+            }
+
+            self.uids.insert(next.to_string());
+            self.users[i].uid = next.to_string();
+        }
+
+        (UserPool { users: self.users }, self.issues)
+    }
+}
+
+fn give_role(user: &mut TwitchUser, role: &str) -> Result<(), Problem> {
+    let tier = |tier| Subscription {
+        tier,
+        ..user.subscription
+    };
+
+    match role.to_lowercase().as_str() {
+        "mod" | "moderator" => user.is_mod = true,
+        "vip" => user.is_vip = true,
+        "sub" | "subscriber" | "tier1" => user.is_sub = true,
+        "tier2" => (user.is_sub, user.subscription) = (true, tier(SubTier::Two)),
+        "tier3" => (user.is_sub, user.subscription) = (true, tier(SubTier::Three)),
+        _ => return Err(Problem::UnknownRole(role.to_string())),
+    }
+
+    Ok(())
+}
+
+/// Splits a CSV line into trimmed fields, where quoted fields may contain commas and `""` for quotes
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("always a field");
+
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    fields
+        .into_iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use usergen::Color;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_login() {
+        assert_eq!(validate_login("SomeOne_42"), Ok(()));
+        assert_eq!(validate_login("abc"), Err(LoginError::TooShort));
+        assert_eq!(validate_login(&"a".repeat(26)), Err(LoginError::TooLong));
+        assert_eq!(
+            validate_login("_someone"),
+            Err(LoginError::LeadingUnderscore)
+        );
+        assert_eq!(
+            validate_login("some one"),
+            Err(LoginError::InvalidChar(' '))
+        );
+        assert_eq!(validate_login("sömeone"), Err(LoginError::InvalidChar('ö')));
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut import = PoolImport::default();
+        import.csv(
+            "users.csv",
+            "uid,name,roles,color
+1234,SomeOne,\"mod | tier3\",#1E90FF
+,another,,
+5678,SOMEONE,vip,
+1234,third_one,,
+,bad name,,
+,fourth,wizard,#nope
+",
+        );
+
+        let (pool, issues) = import.finish();
+
+        assert_eq!(pool.users.len(), 3);
+
+        let someone = &pool.users[0];
+        assert_eq!(someone.uid, "1234");
+        assert_eq!(someone.color, Color::new(0x1E, 0x90, 0xFF));
+        assert!(someone.is_mod && someone.is_sub && !someone.is_vip);
+        assert_eq!(someone.subscription.tier, SubTier::Three);
+
+        assert_eq!(pool.users[1].name, "another");
+        assert_eq!(pool.users[1].uid, FIRST_IMPORTED_UID.to_string());
+        assert_eq!(pool.users[2].name, "fourth");
+
+        let problems: Vec<_> = issues.iter().map(|issue| &issue.problem).collect();
+        assert_eq!(
+            problems,
+            [
+                &Problem::DuplicateName(String::from("SOMEONE")),
+                &Problem::DuplicateUid(String::from("1234")),
+                &Problem::InvalidLogin(String::from("bad name"), LoginError::InvalidChar(' ')),
+                &Problem::InvalidColor(String::from("#nope")),
+                &Problem::UnknownRole(String::from("wizard")),
+            ]
+        );
+        assert_eq!(issues[0].origin, "users.csv:4");
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut import = PoolImport::from_pool(UserPool {
+            users: vec![TwitchUser::new("existing", FIRST_IMPORTED_UID.to_string())],
+        });
+
+        import
+            .usernames("names.txt", "# personas\nnewcomer\n\nexisting\n")
+            .pool(
+                "other.json",
+                UserPool {
+                    users: vec![TwitchUser::new("merged", "42")],
+                },
+            );
+
+        let (pool, issues) = import.finish();
+
+        let names: Vec<_> = pool.users.iter().map(|user| user.name.as_str()).collect();
+        assert_eq!(names, ["existing", "newcomer", "merged"]);
+
+        // The uid taken by the existing user is skipped
+        assert_eq!(pool.users[1].uid, (FIRST_IMPORTED_UID + 1).to_string());
+
+        assert_eq!(
+            issues,
+            [PoolIssue {
+                origin: String::from("names.txt:4"),
+                problem: Problem::DuplicateName(String::from("existing")),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pool_names_are_display_names() {
+        let mut import = PoolImport::default();
+        import.pool(
+            "pool.json",
+            UserPool {
+                users: vec![TwitchUser::new("ジュリエット", "42")],
+            },
+        );
+
+        let (pool, issues) = import.finish();
+
+        assert_eq!(pool.users[0].name, "ジュリエット");
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_merge_import() {
+        let mut files = PoolImport::default();
+        files.usernames("names.txt", "newcomer\nexisting\nbad name\n");
+
+        let mut import = PoolImport::from_pool(UserPool {
+            users: vec![TwitchUser::new("existing", FIRST_IMPORTED_UID.to_string())],
+        });
+        import.merge(files);
+
+        let (pool, issues) = import.finish();
+
+        let names: Vec<_> = pool.users.iter().map(|user| user.name.as_str()).collect();
+        assert_eq!(names, ["existing", "newcomer"]);
+        assert_eq!(pool.users[1].uid, (FIRST_IMPORTED_UID + 1).to_string());
+
+        let origins: Vec<_> = issues.iter().map(|issue| issue.origin.as_str()).collect();
+        assert_eq!(origins, ["names.txt:3", "names.txt:2"]);
+    }
+
+    #[test]
+    fn test_split_csv() {
+        assert_eq!(split_csv("a, b ,c"), ["a", "b", "c"]);
+        assert_eq!(split_csv("\"a, \"\"b\"\"\",,c"), ["a, \"b\"", "", "c"]);
+    }
+}
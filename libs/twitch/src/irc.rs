@@ -0,0 +1,452 @@
+use std::{
+    fmt::{self, Display, Write},
+    str::FromStr,
+};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("The message was empty")]
+    Empty,
+    #[error("The message had tags or a prefix but no command")]
+    MissingCommand,
+    #[error("Found an empty tag key in: {0}")]
+    EmptyTagKey(String),
+}
+
+/// The ordered list of `IRCv3` tags attached to a message
+///
+/// Order is preserved so that generated messages look like the ones Twitch sends
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tags(Vec<(String, String)>);
+
+impl Tags {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the given tag, replacing the value in place if it already exists
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let key = key.into();
+        let value = value.into();
+
+        if let Some(existing) = self.0.iter_mut().find(|(k, _)| k == &key) {
+            existing.1 = value;
+        } else {
+            self.0.push((key, value));
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Display for Tags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_char(';')?;
+            }
+
+            write!(f, "{key}={}", escape_tag_value(value))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes a tag value as described in the [`IRCv3` message tags spec](https://ircv3.net/specs/extensions/message-tags#escaping-values)
+#[must_use]
+pub fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Reverses [`escape_tag_value`]
+///
+/// Unknown escapes are replaced with the escaped character, and a trailing lone backslash is dropped, as per the spec
+#[must_use]
+pub fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+impl FromStr for Tags {
+    type Err = ParseError;
+
+    /// Parses the tags section of a message, without the leading `@`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tags = Tags::new();
+
+        for tag in s.split(';').filter(|tag| !tag.is_empty()) {
+            let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+
+            if key.is_empty() {
+                return Err(ParseError::EmptyTagKey(tag.to_string()));
+            }
+
+            tags.insert(key, unescape_tag_value(value));
+        }
+
+        Ok(tags)
+    }
+}
+
+/// Normalises a channel name to the lowercase, `#` prefixed form used on the wire
+#[must_use]
+pub fn channel_name(channel: &str) -> String {
+    format!("#{}", channel.trim_start_matches('#').to_lowercase())
+}
+
+/// A single IRC message, as sent over the wire by Twitch
+///
+/// Built up by chaining, i.e `IrcMessage::new("PRIVMSG").tag("color", "#FF0000").param("#channel")`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrcMessage {
+    pub tags: Tags,
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    #[must_use]
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            tags: Tags::new(),
+            prefix: None,
+            command: command.into(),
+            params: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(key, value);
+        self
+    }
+
+    #[must_use]
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    #[must_use]
+    pub fn param(mut self, param: impl Into<String>) -> Self {
+        self.params.push(param.into());
+        self
+    }
+
+    /// Creates the prefix Twitch uses for messages originating from a user
+    #[must_use]
+    pub fn user_prefix(login: &str) -> String {
+        format!("{login}!{login}@{login}.tmi.twitch.tv")
+    }
+}
+
+impl Display for IrcMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.tags.is_empty() {
+            write!(f, "@{} ", self.tags)?;
+        }
+
+        if let Some(ref prefix) = self.prefix {
+            write!(f, ":{prefix} ")?;
+        }
+
+        f.write_str(&self.command)?;
+
+        for (i, param) in self.params.iter().enumerate() {
+            let is_last = i == self.params.len() - 1;
+
+            // Twitch always sends the final parameter as a trailing one when there are several,
+            // and it must be trailing if it would otherwise be ambiguous
+            let trailing = is_last
+                && (self.params.len() > 1
+                    || param.is_empty()
+                    || param.starts_with(':')
+                    || param.contains(' '));
+
+            if trailing {
+                write!(f, " :{param}")?;
+            } else {
+                write!(f, " {param}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits off the next space delimited word, skipping any leading spaces
+fn next_word(input: &str) -> (&str, &str) {
+    let input = input.trim_start_matches(' ');
+
+    input.split_once(' ').unwrap_or((input, ""))
+}
+
+impl FromStr for IrcMessage {
+    type Err = ParseError;
+
+    /// Parses a single raw IRC line, as sent by Twitch or a client
+    ///
+    /// Any trailing line ending is ignored
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s.trim_end_matches(['\r', '\n']);
+
+        if rest.trim().is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        let tags = if let Some(stripped) = rest.strip_prefix('@') {
+            let (tags, remaining) = next_word(stripped);
+            rest = remaining;
+            tags.parse()?
+        } else {
+            Tags::new()
+        };
+
+        rest = rest.trim_start_matches(' ');
+
+        let prefix = if let Some(stripped) = rest.strip_prefix(':') {
+            let (prefix, remaining) = next_word(stripped);
+            rest = remaining;
+            Some(prefix.to_string())
+        } else {
+            None
+        };
+
+        let (command, remaining) = next_word(rest);
+        rest = remaining;
+
+        if command.is_empty() {
+            return Err(ParseError::MissingCommand);
+        }
+
+        let mut params = Vec::new();
+
+        loop {
+            rest = rest.trim_start_matches(' ');
+
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+
+            if rest.is_empty() {
+                break;
+            }
+
+            let (param, remaining) = next_word(rest);
+            params.push(param.to_string());
+            rest = remaining;
+        }
+
+        Ok(Self {
+            tags,
+            prefix,
+            command: command.to_string(),
+            params,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_escape_tag_value() {
+        assert_eq!(escape_tag_value("plain"), "plain");
+        assert_eq!(escape_tag_value("two words"), "two\\swords");
+        assert_eq!(escape_tag_value("a;b\\c"), "a\\:b\\\\c");
+        assert_eq!(escape_tag_value("line\r\nbreak"), "line\\r\\nbreak");
+    }
+
+    #[test]
+    fn test_serialize_privmsg() {
+        let msg = IrcMessage::new("PRIVMSG")
+            .tag("display-name", "Some One")
+            .tag("emotes", "")
+            .prefix(IrcMessage::user_prefix("someone"))
+            .param("#channel")
+            .param("Hello World!");
+
+        assert_eq!(
+            msg.to_string(),
+            "@display-name=Some\\sOne;emotes= :someone!someone@someone.tmi.twitch.tv PRIVMSG #channel :Hello World!"
+        );
+    }
+
+    #[test]
+    fn test_serialize_without_trailing() {
+        let msg = IrcMessage::new("JOIN")
+            .prefix(IrcMessage::user_prefix("someone"))
+            .param("#channel");
+
+        assert_eq!(
+            msg.to_string(),
+            ":someone!someone@someone.tmi.twitch.tv JOIN #channel"
+        );
+
+        let msg = IrcMessage::new("PING").param("tmi.twitch.tv");
+
+        assert_eq!(msg.to_string(), "PING tmi.twitch.tv");
+    }
+
+    #[test]
+    fn test_channel_name() {
+        assert_eq!(channel_name("SomeChannel"), "#somechannel");
+        assert_eq!(channel_name("#somechannel"), "#somechannel");
+    }
+
+    #[test]
+    fn test_tags_replace_in_place() {
+        let mut tags = Tags::new();
+        tags.insert("a", "1");
+        tags.insert("b", "2");
+        tags.insert("a", "3");
+
+        assert_eq!(tags.to_string(), "a=3;b=2");
+    }
+
+    #[test]
+    fn test_parse_privmsg() {
+        let line = "@badge-info=;badges=moderator/1;color=#FF4500;display-name=Some\\sOne;emotes=;mod=1 :someone!someone@someone.tmi.twitch.tv PRIVMSG #channel :Hello there :)\r\n";
+
+        let msg: IrcMessage = line.parse().unwrap();
+
+        assert_eq!(msg.command, "PRIVMSG");
+        assert_eq!(
+            msg.prefix.as_deref(),
+            Some("someone!someone@someone.tmi.twitch.tv")
+        );
+        assert_eq!(msg.params, ["#channel", "Hello there :)"]);
+        assert_eq!(msg.tags.get("display-name"), Some("Some One"));
+        assert_eq!(msg.tags.get("badge-info"), Some(""));
+        assert_eq!(msg.tags.len(), 6);
+    }
+
+    #[test]
+    fn test_parse_client_commands() {
+        let msg: IrcMessage = "CAP REQ :twitch.tv/tags twitch.tv/commands"
+            .parse()
+            .unwrap();
+
+        assert_eq!(msg.command, "CAP");
+        assert_eq!(msg.params, ["REQ", "twitch.tv/tags twitch.tv/commands"]);
+
+        let msg: IrcMessage = "NICK justinfan123".parse().unwrap();
+
+        assert_eq!(msg, IrcMessage::new("NICK").param("justinfan123"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("".parse::<IrcMessage>(), Err(ParseError::Empty));
+        assert_eq!("\r\n".parse::<IrcMessage>(), Err(ParseError::Empty));
+        assert_eq!(
+            "@a=b :prefix".parse::<IrcMessage>(),
+            Err(ParseError::MissingCommand)
+        );
+    }
+
+    #[test]
+    fn test_unescape_tag_value() {
+        assert_eq!(unescape_tag_value("two\\swords"), "two words");
+        assert_eq!(unescape_tag_value("a\\:b\\\\c"), "a;b\\c");
+        assert_eq!(unescape_tag_value("unknown\\xescape"), "unknownxescape");
+        assert_eq!(unescape_tag_value("trailing\\"), "trailing");
+    }
+
+    fn arb_message() -> impl Strategy<Value = IrcMessage> {
+        let tags = prop::collection::vec(("[a-z0-9-]{1,12}", "[^\\x00]{0,16}"), 0..6);
+        let prefix = prop::option::of("[a-z0-9_]{1,10}(![a-z0-9_]{1,10}@[a-z0-9_.]{1,20})?");
+        let command = prop_oneof!["[A-Z]{1,10}", "[0-9]{3}"];
+        let middle = prop::collection::vec("[^ :\\r\\n\\x00][^ \\r\\n\\x00]{0,10}", 0..4);
+        let trailing = prop::option::of("[^\\r\\n\\x00]{0,32}");
+
+        (tags, prefix, command, middle, trailing).prop_map(
+            |(tags, prefix, command, middle, trailing)| {
+                let mut msg = IrcMessage::new(command);
+
+                for (key, value) in tags {
+                    msg = msg.tag(key, value);
+                }
+
+                msg.prefix = prefix;
+                msg.params = middle;
+                msg.params.extend(trailing);
+
+                msg
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(msg in arb_message()) {
+            let serialized = msg.to_string();
+            let parsed: IrcMessage = serialized.parse().unwrap();
+
+            prop_assert_eq!(parsed, msg);
+        }
+
+        #[test]
+        fn test_tag_value_round_trip(value in "\\PC*") {
+            prop_assert_eq!(unescape_tag_value(&escape_tag_value(&value)), value);
+        }
+    }
+}
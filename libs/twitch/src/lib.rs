@@ -1,57 +1,37 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::unsafe_derive_deserialize, clippy::missing_errors_doc)]
 
-use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use rand::seq::SliceRandom;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use usergen::Color;
 
+pub use error::TwitchApiError;
+use helix::HelixApi;
+use irc::IrcMessage;
+use randomise::{RandomiseMode, RoleRatios};
+
+pub mod auth;
+pub mod cache;
 pub mod creds;
+pub mod edit;
+pub mod error;
+pub mod export;
+pub mod helix;
+pub mod import;
+pub mod irc;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod randomise;
+pub mod recording;
+pub mod token;
 
 pub static USERS: Mutex<UserPool> = Mutex::new(UserPool { users: Vec::new() });
 
-#[macro_export]
-macro_rules! api_url {
-    ($url:literal) => {
-        format!(
-            "https://api.twitch.tv/helix/{}",
-            format!($url, user_id = env!("TWITCH_USER_ID"))
-        )
-    };
-}
-
-pub static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
-    use reqwest::header::HeaderValue;
-    let mut default_headers = reqwest::header::HeaderMap::new();
-    default_headers.insert(
-        "Client-Id",
-        HeaderValue::from_str(&crate::creds::CREDENTIALS.lock().client_id).unwrap(),
-    );
-    default_headers.insert(
-        "Authorization",
-        HeaderValue::from_str(&format!(
-            "Bearer {}",
-            crate::creds::CREDENTIALS.lock().auth_token
-        ))
-        .unwrap(),
-    );
-
-    reqwest::Client::builder()
-        .default_headers(default_headers)
-        .build()
-        .unwrap()
-});
-
 // Must retrieve list of followers, subscribers, mods, vips, etc. and match against the list of users in the channel
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TwitchVips {
-    pub data: Vec<VipDatum>,
-    pub pagination: Pagination,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VipDatum {
     pub user_id: String,
@@ -59,23 +39,13 @@ pub struct VipDatum {
     pub user_login: String,
 }
 
-impl TwitchVips {
-    async fn from_api(url: String) -> anyhow::Result<Self> {
-        let mut data: TwitchVips = CLIENT.get(&url).send().await?.json().await?;
-
-        while let Some(ref cursor) = data.pagination.cursor {
-            let url = format!("{url}&after={cursor}");
-            let new_data: TwitchVips = {
-                let txt = CLIENT.get(dbg!(url)).send().await?.text().await?;
-                serde_json::from_str(&dbg!(txt))?
-            };
-
-            data.data.extend(new_data.data);
-            data.pagination = new_data.pagination;
-        }
-
-        Ok(data)
-    }
+/// A subscriber, as listed by Helix's `subscriptions` endpoint
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriberDatum {
+    pub user_id: String,
+    pub user_name: String,
+    pub user_login: String,
+    pub tier: SubTier,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -83,7 +53,7 @@ pub struct UserPool {
     pub users: Vec<TwitchUser>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TwitchUser {
     pub name: String,
     pub uid: String,
@@ -91,9 +61,75 @@ pub struct TwitchUser {
     pub is_mod: bool,
     pub is_vip: bool,
     pub is_sub: bool,
+    /// Only shown when `is_sub`, pools saved before this existed are tier 1 for a month
+    #[serde(default)]
+    pub subscription: Subscription,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubTier {
+    #[default]
+    #[serde(rename = "1000")]
+    One,
+    #[serde(rename = "2000")]
+    Two,
+    #[serde(rename = "3000")]
+    Three,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub tier: SubTier,
+    /// How many months in total the user has been subscribed
+    pub months: u32,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            tier: SubTier::One,
+            months: 1,
+        }
+    }
+}
+
+/// The months Twitch has subscriber badges for
+const BADGE_MONTHS: [u32; 16] = [0, 2, 3, 6, 9, 12, 18, 24, 30, 36, 48, 60, 72, 84, 96, 108];
+
+impl Subscription {
+    /// The version of the `subscriber` badge, where higher tiers are offset by 2000 and 3000
+    #[must_use]
+    pub fn badge_version(&self) -> u32 {
+        let months = BADGE_MONTHS
+            .iter()
+            .rev()
+            .find(|&&badge| badge <= self.months)
+            .copied()
+            .unwrap_or_default();
+
+        match self.tier {
+            SubTier::One => months,
+            SubTier::Two => 2000 + months,
+            SubTier::Three => 3000 + months,
+        }
+    }
 }
 
 impl TwitchUser {
+    /// A user without any roles, who is given a color
+    #[must_use]
+    pub fn new(name: impl Into<String>, uid: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            uid: uid.into(),
+            color: Color::generate_light(),
+            is_mod: false,
+            is_vip: false,
+            is_sub: false,
+            subscription: Subscription::default(),
+        }
+    }
+
     /// # Panics
     /// - If the list of users is empty (which it should never be)
     pub fn random() -> Self {
@@ -126,18 +162,9 @@ impl TwitchUser {
             is_mod: false,
             is_vip: false,
             is_sub: rng.gen(),
+            subscription: Subscription::default(),
         }
     }
-
-    // pub fn randomize_properties(&mut self) {
-    //     use rand::Rng;
-
-    //     let mut rng = rand::thread_rng();
-
-    //     self.is_sub = rng.gen_range(1..1000) < 200;
-    //     self.is_vip = rng.gen_range(1..1000) < 50;
-    //     self.is_mod = rng.gen_range(1..1000) < ;
-    // }
 }
 
 pub struct Badges {
@@ -164,7 +191,7 @@ impl Badges {
         }
 
         if user.is_sub {
-            badges.push(Badge::Subscriber);
+            badges.push(Badge::Subscriber(user.subscription));
         }
 
         Self { inner: badges }
@@ -173,7 +200,7 @@ impl Badges {
 
 pub enum Badge {
     Broadcaster,
-    Subscriber,
+    Subscriber(Subscription),
     Moderator,
     Vip,
 }
@@ -182,7 +209,7 @@ impl std::fmt::Display for Badge {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Broadcaster => write!(f, "broadcaster/1"),
-            Self::Subscriber => write!(f, "subscriber/3012"),
+            Self::Subscriber(sub) => write!(f, "subscriber/{}", sub.badge_version()),
             Self::Vip => write!(f, "vip/1"),
             Self::Moderator => write!(f, "moderator/1"),
         }
@@ -191,8 +218,6 @@ impl std::fmt::Display for Badge {
 
 impl std::fmt::Display for Badges {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "badges=")?;
-
         for (i, badge) in self.inner.iter().enumerate() {
             write!(f, "{badge}")?;
 
@@ -201,47 +226,22 @@ impl std::fmt::Display for Badges {
             }
         }
 
-        write!(f, ";")?;
-
         Ok(())
     }
 }
 
 impl TwitchUser {
-    pub fn send_message(&self, message: impl AsRef<str>) -> String {
-        let msg = message.as_ref();
-
+    /// Creates the `PRIVMSG` a real Twitch client would receive for this user sending the given message to the given channel
+    ///
+    /// # Panics
+    /// - If the system time is before the unix epoch
+    pub fn privmsg(&self, channel: &str, message: impl AsRef<str>) -> IrcMessage {
         let badges = Badges::from_user(self);
-
-        let mut message = format!(
-            "@badge-info={};",
-            if self.is_sub { "subscriber/22" } else { "" }
-        );
-
-        message.push_str(badges.to_string().as_str());
-
-        message.push_str("client-nonce=6090b7621f1bf7bdcc46777cd522bca1;");
-
-        message.push_str(&format!("color=#{:X};", self.color));
-
-        message.push_str(&format!("display-name={};", self.name));
-
-        message.push_str("emotes=;first-msg=0;flags=;id=aedfa462-66b6-4a2b-b94d-afb01d0631f9;");
-
-        message.push_str(&format!("mod={};", if self.is_mod { "1" } else { "0" }));
-
-        message.push_str("returning-chatter=0;");
-
-        message.push_str(const_format::concatcp!(
-            "room-id=",
-            env!("TWITCH_USER_ID"),
-            ";"
-        ));
-
-        message.push_str(&format!(
-            "subscriber={};",
-            if self.is_sub { "1" } else { "0" }
-        ));
+        let badge_info = if self.is_sub {
+            format!("subscriber/{}", self.subscription.months)
+        } else {
+            String::new()
+        };
 
         let current_time = {
             use std::time::{SystemTime, UNIX_EPOCH};
@@ -252,152 +252,216 @@ impl TwitchUser {
                 .as_millis()
         };
 
-        message.push_str(&format!("tmi-sent-ts={current_time};"));
+        IrcMessage::new("PRIVMSG")
+            .tag("badge-info", badge_info)
+            .tag("badges", badges.to_string())
+            .tag("client-nonce", "6090b7621f1bf7bdcc46777cd522bca1")
+            .tag("color", format!("#{:X}", self.color))
+            .tag("display-name", &self.name)
+            .tag("emotes", "")
+            .tag("first-msg", "0")
+            .tag("flags", "")
+            .tag("id", "aedfa462-66b6-4a2b-b94d-afb01d0631f9")
+            .tag("mod", if self.is_mod { "1" } else { "0" })
+            .tag("returning-chatter", "0")
+            .tag("room-id", crate::creds::Credentials::read().user_id)
+            .tag("subscriber", if self.is_sub { "1" } else { "0" })
+            .tag("tmi-sent-ts", current_time.to_string())
+            .tag("turbo", "0")
+            .tag("user-id", &self.uid)
+            .tag("user-type", "")
+            .prefix(IrcMessage::user_prefix(&self.name))
+            .param(irc::channel_name(channel))
+            .param(message.as_ref())
+    }
 
-        message.push_str("turbo=0;");
+    pub fn send_message(&self, channel: &str, message: impl AsRef<str>) -> String {
+        self.privmsg(channel, message).to_string()
+    }
+}
 
-        message.push_str(&format!("user-id={};", self.uid));
+/// Who the pool is made of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolSource {
+    /// The broadcaster and everyone following them
+    #[default]
+    Followers,
+    /// Everyone currently in chat
+    Chatters,
+}
 
-        message.push_str("user-type= :");
+#[derive(Debug, Error)]
+#[error("Unknown pool source `{0}`, expected followers or chatters")]
+pub struct UnknownPoolSource(String);
 
-        message.push_str(&format!(
-            "{}!{}@{}.tmi.twitch.tv PRIVMSG #{} :{}",
-            self.name, self.name, self.name, self.name, msg
-        ));
+impl std::str::FromStr for PoolSource {
+    type Err = UnknownPoolSource;
 
-        message
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "followers" => Ok(Self::Followers),
+            "chatters" => Ok(Self::Chatters),
+            _ => Err(UnknownPoolSource(s.to_string())),
+        }
     }
 }
 
 impl UserPool {
-    pub async fn get() -> anyhow::Result<Self> {
-        println!("Downloading pool");
-        println!("Downloading pool");
-        println!("Downloading pool");
-        println!("Downloading pool");
-        println!("Downloading pool");
-        println!("Downloading pool");
-        println!("Downloading pool");
-
-        let vips = TwitchVips::from_api(crate::api_url!(
-            "channels/vips?broadcaster_id={user_id}&first=100"
-        ))
-        .await?;
-
-        let mods = TwitchVips::from_api(crate::api_url!(
-            "moderation/moderators?broadcaster_id={user_id}&first=100"
-        ))
-        .await?;
-
-        let subs = TwitchVips::from_api(crate::api_url!(
-            "subscriptions?broadcaster_id={user_id}&first=100"
-        ))
-        .await?;
-
-        let users = TwitchUsers::new().await?;
-
-        let users = users
-            .data
-            .par_iter()
-            .map(|user| {
-                let mut pooled_user: TwitchUser = TwitchUser {
-                    name: user.from_name.clone(),
-                    uid: user.from_id.clone(),
-                    color: Color::generate_light(),
-                    is_mod: false,
-                    is_vip: false,
-                    is_sub: false,
-                };
+    /// Downloads the pool for the broadcaster the credentials belong to
+    pub async fn get(source: PoolSource) -> Result<Self, TwitchApiError> {
+        let broadcaster_id = creds::Credentials::read().user_id;
 
-                if vips.data.par_iter().any(|vip| vip.user_id == user.from_id) {
-                    pooled_user.is_vip = true;
-                }
+        Self::download(&helix::Helix::default(), &broadcaster_id, source).await
+    }
 
-                if mods
-                    .data
-                    .par_iter()
-                    .any(|moderator| moderator.user_id == user.from_id)
-                {
-                    pooled_user.is_mod = true;
-                }
+    /// Builds the pool from the source, marking who is a mod, VIP or subscriber
+    pub async fn download(
+        api: &impl HelixApi,
+        broadcaster_id: &str,
+        source: PoolSource,
+    ) -> Result<Self, TwitchApiError> {
+        tracing::info!("Downloading pool from {source:?}");
+
+        let vips = api.vips(broadcaster_id).await?;
+        let mods = api.moderators(broadcaster_id).await?;
+        let subs = api.subscribers(broadcaster_id).await?;
+
+        let people: Vec<(String, String)> = match source {
+            PoolSource::Followers => {
+                let broadcaster = api.users(&[broadcaster_id.to_string()]).await?;
+                let followers = api.followers(broadcaster_id).await?;
+
+                broadcaster
+                    .into_iter()
+                    .map(|user| (user.id, user.display_name))
+                    .chain(
+                        followers
+                            .into_iter()
+                            .map(|follower| (follower.user_id, follower.user_name)),
+                    )
+                    .collect()
+            }
+            // The broadcaster is always allowed to moderate their own chat
+            PoolSource::Chatters => api
+                .chatters(broadcaster_id, broadcaster_id)
+                .await?
+                .into_iter()
+                .map(|chatter| (chatter.user_id, chatter.user_name))
+                .collect(),
+        };
 
-                if subs.data.par_iter().any(|sub| sub.user_id == user.from_id) {
-                    pooled_user.is_sub = true;
+        let users = people
+            .into_par_iter()
+            .map(|(uid, name)| {
+                let sub = subs.par_iter().find_any(|sub| sub.user_id == uid);
+
+                TwitchUser {
+                    is_vip: vips.par_iter().any(|vip| vip.user_id == uid),
+                    is_mod: mods.par_iter().any(|moderator| moderator.user_id == uid),
+                    is_sub: sub.is_some(),
+                    subscription: Subscription {
+                        tier: sub.map(|sub| sub.tier).unwrap_or_default(),
+                        ..Subscription::default()
+                    },
+                    name,
+                    uid,
+                    color: Color::generate_light(),
                 }
-
-                pooled_user
             })
             .collect::<Vec<TwitchUser>>();
 
         Ok(UserPool { users })
     }
 
+    /// Builds a pool of `size` made up users along with the broadcaster, without using Twitch at all
+    ///
+    /// Roles are given out at the ratios, the broadcaster never has any.
+    #[must_use]
+    pub fn synthetic(broadcaster: &str, size: usize, ratios: &RoleRatios) -> Self {
+        let mut rng = rand::thread_rng();
+
+        let broadcaster = TwitchUser::new(broadcaster, creds::OFFLINE_USER_ID);
+
+        let uids = (1..).map(|i| (200_000_000 + i).to_string());
+
+        let users: Vec<_> = usergen::generate_usernames(size, &mut rng)
+            .into_iter()
+            .zip(uids)
+            .map(|(name, uid)| {
+                let mut user = TwitchUser::new(name, uid);
+                ratios.randomise(&mut user, RandomiseMode::Replace, &mut rng);
+                user
+            })
+            .collect();
+
+        UserPool {
+            users: std::iter::once(broadcaster).chain(users).collect(),
+        }
+    }
+
     #[allow(clippy::missing_panics_doc)]
-    pub fn send_message(&self, message: impl AsRef<str>) -> String {
+    pub fn send_message(&self, channel: &str, message: impl AsRef<str>) -> String {
         let mut rng = rand::thread_rng();
         let user = self.users.choose(&mut rng).unwrap();
 
-        user.send_message(message)
+        user.send_message(channel, message)
     }
 
-    pub fn send_message_as(&self, message: impl AsRef<str>, user: &TwitchUser) -> String {
-        user.send_message(message)
+    pub fn send_message_as(
+        &self,
+        channel: &str,
+        message: impl AsRef<str>,
+        user: &TwitchUser,
+    ) -> String {
+        user.send_message(channel, message)
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct TwitchUsers {
-    pub total: usize,
-    pub data: Vec<Datum>,
-    pub pagination: Pagination,
+pub struct Follower {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub followed_at: String,
 }
 
-impl TwitchUsers {
-    pub async fn new() -> anyhow::Result<Self> {
-        let mut total = 0;
-        let mut pag_cursor = None;
-        let mut data = vec![];
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pagination {
+    pub cursor: Option<String>,
+}
 
-        loop {
-            let mut url = crate::api_url!("users/follows?to_id={user_id}&first=100").to_string();
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
 
-            if let Some(cursor) = pag_cursor {
-                url.push_str(&format!("&after={cursor}"));
-            }
+    use super::*;
 
-            let result: TwitchUsers = CLIENT.get(url).send().await?.json().await?;
+    #[test]
+    fn test_synthetic() {
+        let pool = UserPool::synthetic("fauxchat", 2000, &RoleRatios::default());
 
-            // Not good to set it every single time but it's fine for now
-            total += result.data.len();
-            data.extend(result.data);
+        assert_eq!(pool.users.len(), 2001);
+        assert_eq!(pool.users[0].name, "fauxchat");
+        assert_eq!(pool.users[0].uid, creds::OFFLINE_USER_ID);
 
-            if let Some(cursor) = result.pagination.cursor {
-                pag_cursor = Some(cursor);
-            } else {
-                break;
-            }
-        }
+        let uids: HashSet<_> = pool.users.iter().map(|user| &user.uid).collect();
+        assert_eq!(uids.len(), pool.users.len());
 
-        Ok(Self {
-            total,
-            data,
-            pagination: Pagination::default(),
-        })
+        // Generous bounds, so this only fails if the ratios are badly off
+        let subs = pool.users.iter().filter(|user| user.is_sub).count();
+        let mods = pool.users.iter().filter(|user| user.is_mod).count();
+        assert!((250..550).contains(&subs), "{subs} subs");
+        assert!((20..120).contains(&mods), "{mods} mods");
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Datum {
-    pub from_id: String,
-    pub from_login: String,
-    pub from_name: String,
-    pub to_id: String,
-    pub to_login: String,
-    pub to_name: String,
-    pub followed_at: String,
-}
+    #[test]
+    fn test_subscriber_badges() {
+        let sub = |tier, months| Subscription { tier, months }.badge_version();
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Pagination {
-    pub cursor: Option<String>,
+        assert_eq!(sub(SubTier::One, 1), 0);
+        assert_eq!(sub(SubTier::One, 14), 12);
+        assert_eq!(sub(SubTier::Two, 3), 2003);
+        assert_eq!(sub(SubTier::Three, 200), 3108);
+    }
 }
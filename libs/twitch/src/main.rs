@@ -1,14 +1,83 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::unsafe_derive_deserialize, clippy::missing_errors_doc)]
 
+use std::path::Path;
+
 use tokio::{fs::File, io::AsyncWriteExt};
-use twitch_api::UserPool;
+use twitch_api::{
+    import::pool::PoolImport,
+    randomise::{RandomiseMode, RoleRatios},
+    PoolSource, UserPool,
+};
+
+const USAGE: &str = "Usage:
+    twitch_api [followers|chatters]
+    twitch_api randomise [replace|noise] [ratios.toml]
+    twitch_api import <pool.json|users.csv|usernames.txt>...";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    twitch_api::creds::Credentials::init().await?;
+    let mut args = std::env::args().skip(1);
+
+    let pool = match args.next().as_deref() {
+        // `twitch_api randomise noise` keeps the real roles in pool.json, giving out more on top
+        Some("randomise") => {
+            let mode = match args.next() {
+                Some(mode) => mode.parse()?,
+                None => RandomiseMode::default(),
+            };
+
+            let ratios = match args.next() {
+                Some(path) => toml::from_str(&tokio::fs::read_to_string(path).await?)?,
+                None => RoleRatios::default(),
+            };
+
+            let mut pool: UserPool =
+                serde_json::from_str(&tokio::fs::read_to_string("pool.json").await?)?;
+
+            pool.randomise(&ratios, mode);
+
+            pool
+        }
+        // `twitch_api import pool.json personas.csv` merges them, keeping whoever comes first
+        Some("import") => {
+            let paths: Vec<_> = args.collect();
+
+            if paths.is_empty() {
+                anyhow::bail!(USAGE);
+            }
+
+            let mut import = PoolImport::default();
+
+            for path in paths {
+                import.file(Path::new(&path))?;
+            }
+
+            let (pool, issues) = import.finish();
+
+            for issue in issues {
+                eprintln!("{issue}");
+            }
+
+            pool
+        }
+        Some("help" | "--help" | "-h") => {
+            println!("{USAGE}");
+
+            return Ok(());
+        }
+        // `twitch_api chatters` builds the pool from everyone currently in chat
+        source => {
+            twitch_api::creds::Credentials::init().await?;
+
+            let source = match source {
+                Some(source) => source.parse()?,
+                None => PoolSource::default(),
+            };
 
-    let pool = UserPool::get().await?;
+            UserPool::get(source).await?
+        }
+    };
 
     let pool_str = serde_json::to_string(&pool)?;
 
@@ -0,0 +1,348 @@
+//! A local stand-in for Twitch, so the API can be used without a network or an account
+//!
+//! [`serve_helix`] answers the Helix endpoints used to download the pool from the fixtures in `fixtures/helix`,
+//! along with the `id.twitch.tv` endpoints used to validate and refresh tokens.
+
+use std::{fmt::Write, sync::Arc};
+
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// The broadcaster the fixtures belong to
+pub const BROADCASTER_ID: &str = "100000001";
+
+/// A request the mock received
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// The path, including the query
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The path without the query
+    #[must_use]
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or_default()
+    }
+
+    /// Every value given for a query parameter
+    #[must_use]
+    pub fn query(&self, key: &str) -> Vec<String> {
+        Url::parse(&format!("http://localhost{}", self.path))
+            .map(|url| {
+                url.query_pairs()
+                    .filter(|(k, _)| k == key)
+                    .map(|(_, v)| v.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// A response from the mock, as a status and JSON body
+pub type Reply = (u16, String);
+
+/// A response from the mock, for when a [`Reply`] needs headers
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl From<Reply> for Response {
+    fn from((status, body): Reply) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+}
+
+/// Serves every request with the given handler, returning the base URL of the server
+pub async fn serve<R: Into<Response>>(
+    handler: impl Fn(&Request) -> R + Send + Sync + 'static,
+) -> std::io::Result<String> {
+    serve_on(TcpListener::bind("127.0.0.1:0").await?, handler)
+}
+
+/// Serves every request to the listener with the given handler, returning the base URL of the server
+pub fn serve_on<R: Into<Response>>(
+    listener: TcpListener,
+    handler: impl Fn(&Request) -> R + Send + Sync + 'static,
+) -> std::io::Result<String> {
+    let addr = listener.local_addr()?;
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let handler = Arc::clone(&handler);
+
+            tokio::spawn(async move {
+                if let Err(e) = respond(stream, handler.as_ref()).await {
+                    tracing::debug!("Mock failed to respond: {e}");
+                }
+            });
+        }
+    });
+
+    Ok(format!("http://{addr}"))
+}
+
+async fn respond<R: Into<Response>>(
+    stream: TcpStream,
+    handler: &impl Fn(&Request) -> R,
+) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        stream.read_line(&mut header).await?;
+
+        match header.trim().split_once(':') {
+            Some((name, value)) => headers.push((name.to_string(), value.trim().to_string())),
+            None => break,
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: String::new(),
+    };
+
+    let content_length = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+    request.body = String::from_utf8_lossy(&body).to_string();
+
+    let Response {
+        status,
+        headers,
+        body,
+    } = handler(&request).into();
+
+    let headers = headers
+        .iter()
+        .fold(String::new(), |mut headers, (name, value)| {
+            let _ = write!(headers, "{name}: {value}\r\n");
+            headers
+        });
+
+    let response = format!(
+        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{headers}Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.get_mut().write_all(response.as_bytes()).await?;
+    stream.get_mut().shutdown().await
+}
+
+/// The error body Twitch responds with
+#[must_use]
+pub fn twitch_error(status: u16, message: &str) -> Reply {
+    (
+        status,
+        json!({ "status": status, "message": message }).to_string(),
+    )
+}
+
+/// The data the mock Helix server answers with
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    pub followers: Vec<Value>,
+    pub chatters: Vec<Value>,
+    pub vips: Vec<Value>,
+    pub moderators: Vec<Value>,
+    pub subscriptions: Vec<Value>,
+    pub users: Vec<Value>,
+    /// The most items returned at once, to exercise pagination
+    pub page_size: usize,
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        let parse = |json: &str| serde_json::from_str(json).expect("fixtures are valid JSON");
+
+        Self {
+            followers: parse(include_str!("../fixtures/helix/followers.json")),
+            chatters: parse(include_str!("../fixtures/helix/chatters.json")),
+            vips: parse(include_str!("../fixtures/helix/vips.json")),
+            moderators: parse(include_str!("../fixtures/helix/moderators.json")),
+            subscriptions: parse(include_str!("../fixtures/helix/subscriptions.json")),
+            users: parse(include_str!("../fixtures/helix/users.json")),
+            page_size: 100,
+        }
+    }
+}
+
+impl Fixtures {
+    /// Answers a request as Helix or `id.twitch.tv` would
+    #[must_use]
+    pub fn reply(&self, req: &Request) -> Reply {
+        match req.route() {
+            "/oauth2/validate" => {
+                return (
+                    200,
+                    json!({
+                        "client_id": "mock",
+                        "login": "fauxchat",
+                        "scopes": crate::auth::SCOPES,
+                        "user_id": BROADCASTER_ID,
+                        "expires_in": 14400,
+                    })
+                    .to_string(),
+                )
+            }
+            "/oauth2/token" => {
+                return (
+                    200,
+                    json!({
+                        "access_token": "mock-access",
+                        "refresh_token": "mock-refresh",
+                        "expires_in": 14400,
+                        "scope": crate::auth::SCOPES,
+                        "token_type": "bearer",
+                    })
+                    .to_string(),
+                )
+            }
+            _ => (),
+        }
+
+        let authorized = req.header("client-id").is_some()
+            && req
+                .header("authorization")
+                .is_some_and(|auth| auth.starts_with("Bearer"));
+
+        if !authorized {
+            return twitch_error(401, "OAuth token is missing");
+        }
+
+        let broadcaster = req.query("broadcaster_id");
+        let broadcaster = broadcaster.first().map(String::as_str);
+
+        match req.route() {
+            "/helix/channels/followers" if broadcaster == Some(BROADCASTER_ID) => {
+                self.page(req, &self.followers)
+            }
+            "/helix/chat/chatters" if broadcaster == Some(BROADCASTER_ID) => {
+                if req.query("moderator_id").first().map(String::as_str) == Some(BROADCASTER_ID) {
+                    self.page(req, &self.chatters)
+                } else {
+                    twitch_error(
+                        403,
+                        "The user in moderator_id is not one of the broadcaster's moderators",
+                    )
+                }
+            }
+            "/helix/channels/vips" if broadcaster == Some(BROADCASTER_ID) => {
+                self.page(req, &self.vips)
+            }
+            "/helix/moderation/moderators" if broadcaster == Some(BROADCASTER_ID) => {
+                self.page(req, &self.moderators)
+            }
+            "/helix/subscriptions" if broadcaster == Some(BROADCASTER_ID) => {
+                self.page(req, &self.subscriptions)
+            }
+            "/helix/users" => {
+                let ids = req.query("id");
+                let logins = req.query("login");
+
+                let data: Vec<_> = self
+                    .users
+                    .iter()
+                    .filter(|user| {
+                        ids.iter().any(|id| user["id"] == id.as_str())
+                            || logins.iter().any(|login| user["login"] == login.as_str())
+                    })
+                    .collect();
+
+                (200, json!({ "data": data }).to_string())
+            }
+            "/helix/channels/followers"
+            | "/helix/chat/chatters"
+            | "/helix/channels/vips"
+            | "/helix/moderation/moderators"
+            | "/helix/subscriptions" => twitch_error(403, "broadcaster_id must match the token"),
+            _ => twitch_error(404, "Not Found"),
+        }
+    }
+
+    /// Returns one page of the items, continuing from the `after` cursor
+    fn page(&self, req: &Request, items: &[Value]) -> Reply {
+        let first = req
+            .query("first")
+            .first()
+            .and_then(|first| first.parse().ok())
+            .unwrap_or(20)
+            .min(self.page_size);
+
+        let start = req
+            .query("after")
+            .first()
+            .and_then(|after| after.parse().ok())
+            .unwrap_or(0)
+            .min(items.len());
+
+        let end = (start + first).min(items.len());
+
+        let pagination = if end < items.len() {
+            json!({ "cursor": end.to_string() })
+        } else {
+            json!({})
+        };
+
+        (
+            200,
+            json!({
+                "total": items.len(),
+                "data": &items[start..end],
+                "pagination": pagination,
+            })
+            .to_string(),
+        )
+    }
+}
+
+/// Serves the fixtures as Helix at `<url>/helix` and `id.twitch.tv` at `<url>`, returning the base URL
+pub async fn serve_helix(fixtures: Fixtures) -> std::io::Result<String> {
+    serve(move |req| fixtures.reply(req)).await
+}
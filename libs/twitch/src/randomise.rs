@@ -0,0 +1,209 @@
+//! Gives out roles at random, so a pool looks like a busier channel than the one it was downloaded from
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{SubTier, Subscription, TwitchUser, UserPool};
+
+/// How often each role is given out, as the chance of any one user having it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoleRatios {
+    pub moderator: f64,
+    pub vip: f64,
+    pub subscriber: f64,
+    /// How subscribers are split between tiers 1, 2 and 3, as relative weights
+    pub tiers: [f64; 3],
+    /// The average number of months someone has been subscribed, where shorter subscriptions are more common
+    pub mean_months: f64,
+    /// The most months anyone has been subscribed
+    pub max_months: u32,
+}
+
+impl Default for RoleRatios {
+    fn default() -> Self {
+        Self {
+            moderator: 0.03,
+            vip: 0.06,
+            subscriber: 0.2,
+            tiers: [0.9, 0.04, 0.06],
+            mean_months: 8.0,
+            max_months: 120,
+        }
+    }
+}
+
+/// What happens to the roles users already have
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomiseMode {
+    /// Every role is given out again, ignoring who really has it
+    #[default]
+    Replace,
+    /// Real roles are kept, with more given out on top
+    Noise,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown randomise mode `{0}`, expected replace or noise")]
+pub struct UnknownRandomiseMode(String);
+
+impl std::str::FromStr for RandomiseMode {
+    type Err = UnknownRandomiseMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "replace" => Ok(Self::Replace),
+            "noise" => Ok(Self::Noise),
+            _ => Err(UnknownRandomiseMode(s.to_string())),
+        }
+    }
+}
+
+impl RoleRatios {
+    /// Picks a tier and how long the subscription has lasted
+    pub fn subscription(&self, rng: &mut impl Rng) -> Subscription {
+        // Weights that are all zero or negative can't be picked from, so everyone is tier 1
+        let tier = WeightedIndex::new(self.tiers.map(|weight| weight.max(0.0))).map_or(
+            SubTier::One,
+            |tiers| match tiers.sample(rng) {
+                0 => SubTier::One,
+                1 => SubTier::Two,
+                _ => SubTier::Three,
+            },
+        );
+
+        // Exponentially distributed, as most subscriptions are recent
+        let months = -self.mean_months.max(1.0) * (1.0 - rng.gen::<f64>()).ln();
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let months = (months.ceil() as u32).clamp(1, self.max_months.max(1));
+
+        Subscription { tier, months }
+    }
+
+    /// Gives the user roles at these ratios
+    pub fn randomise(&self, user: &mut TwitchUser, mode: RandomiseMode, rng: &mut impl Rng) {
+        let mut roll = |chance: f64| rng.gen_bool(chance.clamp(0.0, 1.0));
+
+        let (is_mod, is_vip, is_sub) =
+            (roll(self.moderator), roll(self.vip), roll(self.subscriber));
+
+        match mode {
+            RandomiseMode::Replace => {
+                user.is_mod = is_mod;
+                user.is_vip = is_vip;
+                user.is_sub = is_sub;
+                user.subscription = self.subscription(rng);
+            }
+            RandomiseMode::Noise => {
+                user.is_mod |= is_mod;
+                user.is_vip |= is_vip;
+
+                // Twitch only says who subscribes, so real subscribers keep their tier but not their months
+                let subscription = self.subscription(rng);
+
+                if user.is_sub {
+                    user.subscription.months = subscription.months;
+                } else if is_sub {
+                    user.is_sub = true;
+                    user.subscription = subscription;
+                }
+            }
+        }
+    }
+}
+
+impl UserPool {
+    /// Gives everyone in the pool roles at the ratios
+    pub fn randomise(&mut self, ratios: &RoleRatios, mode: RandomiseMode) {
+        let mut rng = rand::thread_rng();
+
+        for user in &mut self.users {
+            ratios.randomise(user, mode, &mut rng);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(size: usize) -> UserPool {
+        UserPool {
+            users: (0..size)
+                .map(|i| TwitchUser {
+                    is_mod: i == 0,
+                    is_sub: i == 0,
+                    subscription: Subscription {
+                        tier: SubTier::Three,
+                        months: 1,
+                    },
+                    ..TwitchUser::new(format!("user{i}"), i.to_string())
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut pool = pool(100);
+
+        pool.randomise(
+            &RoleRatios {
+                moderator: 0.0,
+                vip: 1.0,
+                subscriber: 1.0,
+                tiers: [0.0, 1.0, 0.0],
+                ..RoleRatios::default()
+            },
+            RandomiseMode::Replace,
+        );
+
+        for user in pool.users {
+            assert!(!user.is_mod && user.is_vip && user.is_sub);
+            assert_eq!(user.subscription.tier, SubTier::Two);
+        }
+    }
+
+    #[test]
+    fn test_noise_keeps_real_roles() {
+        let mut pool = pool(100);
+
+        pool.randomise(
+            &RoleRatios {
+                moderator: 0.0,
+                vip: 0.0,
+                subscriber: 0.0,
+                ..RoleRatios::default()
+            },
+            RandomiseMode::Noise,
+        );
+
+        assert!(pool.users[0].is_mod && pool.users[0].is_sub);
+        assert_eq!(pool.users[0].subscription.tier, SubTier::Three);
+        assert!(pool.users[1..]
+            .iter()
+            .all(|user| !user.is_mod && !user.is_vip && !user.is_sub));
+    }
+
+    #[test]
+    fn test_subscription_months() {
+        let ratios = RoleRatios {
+            tiers: [0.0; 3],
+            max_months: 24,
+            ..RoleRatios::default()
+        };
+        let mut rng = rand::thread_rng();
+
+        let subs: Vec<_> = (0..2000).map(|_| ratios.subscription(&mut rng)).collect();
+
+        assert!(subs.iter().all(|sub| sub.tier == SubTier::One));
+        assert!(subs.iter().all(|sub| (1..=24).contains(&sub.months)));
+
+        // Most subscriptions are shorter than the average
+        let short = subs.iter().filter(|sub| sub.months <= 8).count();
+        assert!(short > 1000, "{short} of 2000 are short");
+    }
+}
@@ -0,0 +1,245 @@
+//! Lossless recordings of a session, stored as JSON Lines
+//!
+//! Each line is a [`RecordedEvent`], holding the exact IRC line sent to clients along with the full user that sent it,
+//! so that a session can be replayed exactly as it happened.
+
+use std::{
+    io::{BufRead, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    irc::{IrcMessage, ParseError},
+    TwitchUser,
+};
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("Failed to interact with the recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to serialize event: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("Invalid event on line {line}: {source}")]
+    InvalidEvent {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("Recorded IRC message is invalid: {0}")]
+    InvalidIrc(#[from] ParseError),
+}
+
+/// A single message emitted during a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Unix time in milliseconds the message was sent at
+    pub timestamp: u64,
+    /// Milliseconds since the recording started
+    pub elapsed: u64,
+    pub channel: String,
+    pub message: String,
+    pub user: TwitchUser,
+    /// The exact IRC line sent to clients
+    pub irc: String,
+}
+
+impl RecordedEvent {
+    /// Parses the recorded IRC line back into a message
+    pub fn irc_message(&self) -> Result<IrcMessage, ParseError> {
+        self.irc.parse()
+    }
+
+    /// How long after the start of the recording this event happened
+    #[must_use]
+    pub fn offset(&self) -> Duration {
+        Duration::from_millis(self.elapsed)
+    }
+}
+
+/// The current unix time in milliseconds
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| {
+            u64::try_from(time.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Appends events to a recording as they are emitted
+pub struct Recorder<W> {
+    writer: W,
+    /// Unix time in milliseconds the recording started at
+    started: u64,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording now
+    pub fn new(writer: W) -> Self {
+        Self::starting_at(writer, unix_millis())
+    }
+
+    /// Starts a recording at the given unix time in milliseconds
+    pub fn starting_at(writer: W, started: u64) -> Self {
+        Self { writer, started }
+    }
+
+    /// Records a message sent by the given user, returning the written event
+    ///
+    /// The time is taken from the `tmi-sent-ts` tag when present, so that it matches what clients saw.
+    pub fn record(
+        &mut self,
+        user: &TwitchUser,
+        irc: &IrcMessage,
+    ) -> Result<RecordedEvent, RecordingError> {
+        let timestamp = irc
+            .tags
+            .get("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .unwrap_or_else(unix_millis);
+
+        let channel = irc.params.first().cloned().unwrap_or_default();
+        let message = irc.params.last().cloned().unwrap_or_default();
+
+        let event = RecordedEvent {
+            timestamp,
+            elapsed: timestamp.saturating_sub(self.started),
+            channel,
+            message,
+            user: user.clone(),
+            irc: irc.to_string(),
+        };
+
+        serde_json::to_writer(&mut self.writer, &event)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+
+        Ok(event)
+    }
+}
+
+/// Writes a whole recording at once, such as one that was imported
+pub fn write(mut writer: impl Write, events: &[RecordedEvent]) -> Result<(), RecordingError> {
+    for event in events {
+        serde_json::to_writer(&mut writer, event)?;
+        writeln!(writer)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads every event from a recording, skipping blank lines
+pub fn read(reader: impl BufRead) -> Result<Vec<RecordedEvent>, RecordingError> {
+    let mut events = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event = serde_json::from_str(&line).map_err(|source| RecordingError::InvalidEvent {
+            line: i + 1,
+            source,
+        })?;
+
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// Turns recorded events into the messages to replay, each with its delay from the first event
+///
+/// Events are ordered by when they happened, in case the recording was edited by hand.
+pub fn replay(
+    mut events: Vec<RecordedEvent>,
+) -> Result<Vec<(Duration, IrcMessage)>, RecordingError> {
+    events.sort_by_key(|event| event.elapsed);
+
+    let first = events.first().map_or(Duration::ZERO, RecordedEvent::offset);
+
+    events
+        .iter()
+        .map(|event| Ok((event.offset().saturating_sub(first), event.irc_message()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> TwitchUser {
+        TwitchUser {
+            is_mod: true,
+            is_sub: true,
+            ..TwitchUser::new("someone", "1234")
+        }
+    }
+
+    fn message(text: &str, ts: u64) -> IrcMessage {
+        IrcMessage::new("PRIVMSG")
+            .tag("color", "#FF00FF")
+            .tag("id", "aedfa462-66b6-4a2b-b94d-afb01d0631f9")
+            .tag("tmi-sent-ts", ts.to_string())
+            .prefix(IrcMessage::user_prefix("someone"))
+            .param("#fauxchat")
+            .param(text)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let user = user();
+        let mut recorder = Recorder::starting_at(Vec::new(), 1_000);
+
+        let first = recorder.record(&user, &message("Hey!", 1_250)).unwrap();
+        let second = recorder
+            .record(&user, &message("Did you say \"hey\"?", 2_000))
+            .unwrap();
+
+        assert_eq!(first.elapsed, 250);
+        assert_eq!(second.elapsed, 1_000);
+        assert_eq!(second.channel, "#fauxchat");
+        assert_eq!(second.message, "Did you say \"hey\"?");
+
+        let events = read(recorder.writer.as_slice()).unwrap();
+
+        assert_eq!(events, vec![first, second]);
+    }
+
+    #[test]
+    fn test_replay_is_exact() {
+        let user = user();
+        let mut recorder = Recorder::starting_at(Vec::new(), 1_000);
+
+        let sent = [message("Hey!", 1_250), message("Hello world!", 4_250)];
+
+        for msg in &sent {
+            recorder.record(&user, msg).unwrap();
+        }
+
+        let replayed = replay(read(recorder.writer.as_slice()).unwrap()).unwrap();
+
+        assert_eq!(
+            replayed,
+            vec![
+                (Duration::ZERO, sent[0].clone()),
+                (Duration::from_secs(3), sent[1].clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_line() {
+        let error = read("\n{}\n".as_bytes()).unwrap_err();
+
+        assert!(matches!(
+            error,
+            RecordingError::InvalidEvent { line: 2, .. }
+        ));
+    }
+}
@@ -0,0 +1,203 @@
+//! Keeps the Twitch API token fresh, and makes requests with whichever token is current
+//!
+//! The token is read from [`CREDENTIALS`] for every request, so once it is refreshed every request after uses the new one.
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use tokio::task::JoinHandle;
+
+use crate::{
+    auth::{AuthError, OAuth, TWITCH_ID_URL},
+    creds::{Credentials, CREDENTIALS},
+    TwitchApiError,
+};
+
+/// The client used for every request to the Twitch API
+pub static CLIENT: Lazy<Client> = Lazy::new(Client::default);
+
+/// How often the token is validated, Twitch requires at least hourly
+pub const VALIDATE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// How close to expiring the token is refreshed
+pub const REFRESH_WITHIN: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    id_url: String,
+    /// Whether refreshed credentials are saved to the credentials file, or only kept in memory
+    persist: bool,
+    /// Held while refreshing, so requests failing together only refresh once
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            id_url: std::env::var("FAUXCHAT_ID_URL").unwrap_or_else(|_| TWITCH_ID_URL.to_string()),
+            persist: true,
+            refreshing: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl Client {
+    /// Refreshes tokens somewhere other than `id.twitch.tv`, such as [`crate::mock`]
+    #[must_use]
+    pub fn with_id_url(mut self, id_url: impl Into<String>) -> Self {
+        self.id_url = id_url.into();
+        self
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response, TwitchApiError> {
+        self.send(|http| http.get(url)).await
+    }
+
+    /// Sends the request with the current token, refreshing it and trying again if Twitch rejects it
+    pub async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> Result<Response, TwitchApiError> {
+        let creds = Credentials::read();
+
+        let resp = authorize(request(&self.http), &creds).send().await?;
+
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        tracing::debug!("Twitch rejected the token, refreshing it");
+
+        let creds = self.refresh(&creds).await?;
+
+        Ok(authorize(request(&self.http), &creds).send().await?)
+    }
+
+    fn oauth(&self, creds: &Credentials) -> OAuth {
+        creds.oauth().with_base_url(&self.id_url)
+    }
+
+    /// Refreshes the token, unless it has already been replaced since `stale` was read
+    pub async fn refresh(&self, stale: &Credentials) -> Result<Credentials, AuthError> {
+        let _guard = self.refreshing.lock().await;
+
+        let creds = Credentials::read();
+
+        if creds.auth_token != stale.auth_token {
+            return Ok(creds);
+        }
+
+        let creds = self.oauth(&creds).refresh(&creds).await?;
+
+        *CREDENTIALS.lock() = creds.clone();
+
+        // The new token works either way, it just has to be refreshed again next time
+        if self.persist {
+            if let Err(e) = creds.save() {
+                tracing::warn!("Failed to save the refreshed token: {e}");
+            }
+        }
+
+        Ok(creds)
+    }
+
+    /// Validates the token, refreshing it if it is invalid or close to expiring
+    pub async fn check(&self) -> Result<(), AuthError> {
+        let creds = Credentials::read();
+
+        match self.oauth(&creds).validate(&creds.auth_token).await {
+            Ok(validation) if Duration::from_secs(validation.expires_in) > REFRESH_WITHIN => {
+                return Ok(());
+            }
+            Ok(_) | Err(AuthError::Twitch { .. }) => (),
+            Err(e) => return Err(e),
+        }
+
+        self.refresh(&creds).await?;
+
+        Ok(())
+    }
+
+    /// Checks the token every `interval` for as long as the app runs
+    pub fn spawn_refresh(&'static self, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if let Err(e) = self.check().await {
+                    tracing::warn!("Failed to refresh the Twitch API token: {e}");
+                }
+            }
+        })
+    }
+}
+
+fn authorize(request: RequestBuilder, creds: &Credentials) -> RequestBuilder {
+    request
+        .header("Client-Id", &creds.client_id)
+        .bearer_auth(&creds.auth_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::mock;
+
+    #[tokio::test]
+    async fn test_refresh_on_unauthorized() {
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&refreshes);
+
+        let base_url = mock::serve(move |req| match req.path.as_str() {
+            "/oauth2/token" => {
+                assert!(req.body.contains("grant_type=refresh_token"));
+                assert!(req.body.contains("refresh_token=old-refresh"));
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                (
+                    200,
+                    r#"{"access_token":"new","refresh_token":"new-refresh"}"#.to_string(),
+                )
+            }
+            "/helix/users" if req.header("authorization") == Some("Bearer new") => {
+                (200, r#"{"data":[]}"#.to_string())
+            }
+            "/helix/users" => mock::twitch_error(401, "Invalid OAuth token"),
+            _ => (404, String::new()),
+        })
+        .await
+        .unwrap();
+
+        let client = Client {
+            persist: false,
+            ..Client::default().with_id_url(&base_url)
+        };
+
+        {
+            let mut creds = CREDENTIALS.lock();
+            creds.auth_token = String::from("old");
+            creds.refresh_token = String::from("old-refresh");
+        }
+
+        let url = format!("{base_url}/helix/users");
+        let (first, second) = tokio::join!(client.get(&url), client.get(&url));
+
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.unwrap().status(), StatusCode::OK);
+
+        // Both requests failed with the old token, but it is only refreshed once
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+
+        let creds = Credentials::read();
+        assert_eq!(creds.auth_token, "new");
+        assert_eq!(creds.refresh_token, "new-refresh");
+    }
+}
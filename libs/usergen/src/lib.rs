@@ -1,9 +1,12 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::unsafe_derive_deserialize, clippy::missing_errors_doc)]
 
+use std::{collections::HashSet, str::FromStr};
+
 use openai::{completions::Completion, set_key};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 const PROMPT: &str = "Give me a twitch username for someone who enjoys gaming. It should not include the word gaming, it should not end with numbers. it should be creative, like a mix of two words or someones name, but absolutely should not just combine two words. Present just the username and no other text.";
 const EMPTY: String = String::new();
@@ -29,7 +32,82 @@ pub async fn generate_names<const N: usize>() -> anyhow::Result<[String; N]> {
     Ok(names)
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+const ADJECTIVES: [&str; 40] = [
+    "sleepy", "cosmic", "salty", "fuzzy", "lucky", "silent", "crispy", "spicy", "grumpy", "shiny",
+    "wobbly", "sneaky", "mighty", "lazy", "frosty", "toasty", "chaotic", "humble", "swift",
+    "rusty", "golden", "pixel", "tiny", "wild", "brave", "clumsy", "cozy", "dizzy", "electric",
+    "feral", "gentle", "hollow", "jolly", "lunar", "misty", "noble", "quiet", "rapid", "solar",
+    "velvet",
+];
+
+const NOUNS: [&str; 40] = [
+    "otter", "pancake", "wizard", "falcon", "noodle", "badger", "comet", "goblin", "penguin",
+    "taco", "raccoon", "knight", "biscuit", "dragon", "pickle", "lynx", "muffin", "phantom",
+    "walrus", "cactus", "ferret", "gecko", "hamster", "island", "jackal", "koala", "lantern",
+    "mango", "nebula", "owl", "panda", "quokka", "rocket", "squid", "tiger", "unicorn", "viking",
+    "waffle", "yeti", "zebra",
+];
+
+const NAMES: [&str; 24] = [
+    "alex", "sam", "jordan", "casey", "riley", "morgan", "jamie", "taylor", "robin", "quinn",
+    "avery", "charlie", "devon", "emery", "finley", "harper", "kai", "logan", "max", "nico",
+    "parker", "reese", "skyler", "toby",
+];
+
+fn pick(rng: &mut impl Rng, words: &[&str]) -> String {
+    words[rng.gen_range(0..words.len())].to_string()
+}
+
+/// Generates a username without any network access, following Twitch's rules for logins
+///
+/// Usernames are between 4 and 25 characters long, and only use letters, digits and underscores.
+pub fn generate_username(rng: &mut impl Rng) -> String {
+    let capitalise = |word: String| -> String {
+        let mut chars = word.chars();
+
+        chars.next().map_or_else(String::new, |first| {
+            first.to_uppercase().chain(chars).collect()
+        })
+    };
+
+    let mut name = match rng.gen_range(0..4) {
+        0 => capitalise(pick(rng, &ADJECTIVES)) + &capitalise(pick(rng, &NOUNS)),
+        1 => format!("{}_{}", pick(rng, &ADJECTIVES), pick(rng, &NOUNS)),
+        2 => capitalise(pick(rng, &NAMES)) + &capitalise(pick(rng, &NOUNS)),
+        _ => pick(rng, &NAMES) + &pick(rng, &NOUNS),
+    };
+
+    if rng.gen_bool(0.3) {
+        name += &rng.gen_range(1..100).to_string();
+    }
+
+    name
+}
+
+/// Generates `count` usernames offline, where no two share a login
+pub fn generate_usernames(count: usize, rng: &mut impl Rng) -> Vec<String> {
+    let mut logins = HashSet::with_capacity(count);
+    let mut names = Vec::with_capacity(count);
+
+    while names.len() < count {
+        let mut name = generate_username(rng);
+
+        // Every combination gets used up in large pools, so numbers keep them unique
+        while logins.contains(&name.to_lowercase()) {
+            name = format!("{name}{}", rng.gen_range(0..10));
+        }
+
+        name.truncate(25);
+
+        if logins.insert(name.to_lowercase()) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Color {
     r: u8,
     g: u8,
@@ -37,6 +115,11 @@ pub struct Color {
 }
 
 impl Color {
+    #[must_use]
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
     #[must_use]
     pub fn generate() -> Self {
         let mut rng = rand::thread_rng();
@@ -75,6 +158,29 @@ impl Color {
     }
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Invalid color {0:?}, expected #RRGGBB")]
+pub struct ParseColorError(String);
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    /// Parses a color written as `#RRGGBB`, as Twitch sends them
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseColorError(s.to_string());
+
+        let hex = s.strip_prefix('#').unwrap_or(s);
+
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(err());
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| err());
+
+        Ok(Self::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
 impl std::fmt::UpperHex for Color {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:02X}", self.r)?;
@@ -99,6 +205,34 @@ mod tests {
     //     Ok(())
     // }
 
+    #[test]
+    fn test_parse_color() {
+        let color: Color = "#1E90FF".parse().unwrap();
+
+        assert_eq!(color, Color::new(0x1E, 0x90, 0xFF));
+        assert_eq!(format!("#{color:X}"), "#1E90FF");
+
+        assert!("#1E90F".parse::<Color>().is_err());
+        assert!("#GGGGGG".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_generate_usernames() {
+        let names = generate_usernames(5000, &mut rand::thread_rng());
+
+        let logins: HashSet<_> = names.iter().map(|name| name.to_lowercase()).collect();
+        assert_eq!(logins.len(), names.len());
+
+        for name in names {
+            assert!((4..=25).contains(&name.len()), "{name}");
+            assert!(!name.starts_with('_'), "{name}");
+            assert!(
+                name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "{name}"
+            );
+        }
+    }
+
     #[test]
     fn test_generate_color() {
         let light_color = Color::generate_light();
@@ -0,0 +1,415 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use commands::{amount::Amount, Command};
+use twitch_api::{
+    auth::OAuth,
+    creds::{Credentials, Store},
+    export::{self, ExportFormat, SubtitleOptions},
+    recording::RecordedEvent,
+};
+
+use crate::sessions;
+
+const USAGE: &str = "Usage:
+    fauxchat setup
+    fauxchat convert <file.cmdir> [output.commands]
+    fauxchat import <chat.log|chat.json> [output]
+    fauxchat export <recording.jsonl> <srt|ass|json|csv> [output]
+    fauxchat sessions [list|show <id>|delete <id>|name <id> [name]|tag <id> <tags...>|untag <id> <tags...>|prune]";
+
+/// Runs a subcommand given on the command line, or [`None`] if the app should start normally
+pub async fn run(mut args: impl Iterator<Item = String>) -> Option<anyhow::Result<()>> {
+    match args.next()?.as_str() {
+        "convert" => {
+            let Some(input) = args.next() else {
+                return Some(Err(anyhow::anyhow!(USAGE)));
+            };
+
+            let input = PathBuf::from(input);
+            let output = args
+                .next()
+                .map_or_else(|| input.with_extension("commands"), PathBuf::from);
+
+            Some(convert_cmdir_to(&input, &output).map(|()| {
+                println!("Converted {} into {}", input.display(), output.display());
+            }))
+        }
+        "import" => {
+            let Some(input) = args.next() else {
+                return Some(Err(anyhow::anyhow!(USAGE)));
+            };
+
+            let input = PathBuf::from(input);
+            let output = args.next().map_or_else(|| input.clone(), PathBuf::from);
+
+            Some(import_chat_log(&input, &output).map(|(recording, script)| {
+                println!(
+                    "Imported {} into {} and {}",
+                    input.display(),
+                    recording.display(),
+                    script.display()
+                );
+            }))
+        }
+        "export" => {
+            let (Some(input), Some(format)) = (args.next(), args.next()) else {
+                return Some(Err(anyhow::anyhow!(USAGE)));
+            };
+
+            let input = PathBuf::from(input);
+
+            Some(
+                format
+                    .parse::<ExportFormat>()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|format| {
+                        let output = args.next().map_or_else(
+                            || input.with_extension(format.extension()),
+                            PathBuf::from,
+                        );
+
+                        export_recording(&input, format, &output)?;
+                        println!("Exported {} into {}", input.display(), output.display());
+
+                        Ok(())
+                    }),
+            )
+        }
+        "setup" => Some(setup().await),
+        "sessions" => Some(manage_sessions(&args.collect::<Vec<_>>())),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+
+            Some(Ok(()))
+        }
+        // Anything else is left for tauri to handle
+        _ => None,
+    }
+}
+
+/// Where Twitch redirects back to when logging in through the browser, which must be registered for the application
+const REDIRECT_URI: &str = "http://localhost:17563";
+
+/// Logs in to Twitch, and saves the credentials for the app to use
+async fn setup() -> anyhow::Result<()> {
+    use std::io::Write;
+
+    crate::select_credentials_store();
+
+    let store = Store::current()?;
+
+    println!(
+        "Saving Twitch credentials to {} ({store} store)",
+        Credentials::location(store)
+    );
+
+    if store == Store::Encrypted && std::env::var("FAUXCHAT_PASSPHRASE").is_err() {
+        let passphrase = rpassword::prompt_password("Passphrase: ")?;
+
+        if passphrase.is_empty() {
+            anyhow::bail!("Passphrase is required");
+        }
+
+        Store::set_passphrase(passphrase);
+    }
+    println!("The client id and secret are from an application made at https://dev.twitch.tv/console/apps");
+
+    let prompt = |field: &str| -> anyhow::Result<String> {
+        print!("{field}: ");
+        std::io::stdout().flush()?;
+
+        let mut value = String::new();
+        std::io::stdin().read_line(&mut value)?;
+
+        let value = value.trim().to_string();
+
+        if value.is_empty() {
+            anyhow::bail!("{field} is required");
+        }
+
+        Ok(value)
+    };
+
+    let client_id = prompt("Client id")?;
+    let client_secret = prompt("Client secret")?;
+
+    println!("How do you want to log in?");
+    println!("  1. Enter a code on twitch.tv, from any device");
+    println!(
+        "  2. Log in through the browser, with {REDIRECT_URI} as a redirect URL of the application"
+    );
+    println!("  3. Enter the user id and tokens yourself");
+
+    let oauth = OAuth::new(&client_id, &client_secret);
+
+    let creds = match prompt("Choice")?.as_str() {
+        "1" => {
+            let code = oauth.start_device_flow().await?;
+
+            println!(
+                "Go to {} and enter the code {}",
+                code.verification_uri, code.user_code
+            );
+
+            oauth.finish_device_flow(&code).await?
+        }
+        "2" => {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:17563").await?;
+
+            oauth
+                .authorization_code_flow(listener, REDIRECT_URI, |url| {
+                    println!("Go to {url} to log in");
+                })
+                .await?
+        }
+        "3" => Credentials {
+            client_id,
+            client_secret,
+            user_id: prompt("User id")?,
+            auth_token: prompt("Auth token")?,
+            refresh_token: prompt("Refresh token")?,
+        },
+        choice => anyhow::bail!("{choice} is not one of the choices"),
+    };
+
+    creds.save()?;
+
+    println!("Saved credentials");
+
+    Ok(())
+}
+
+/// Lists and changes the sessions recorded into the cache dir
+fn manage_sessions(args: &[String]) -> anyhow::Result<()> {
+    let dir = sessions::dir();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        [] | ["list"] => {
+            for session in sessions::list(&dir)? {
+                let name = session.name.as_deref().unwrap_or("");
+                let tags = session.tags.join(", ");
+
+                println!(
+                    "{}\t{} messages\t{name}\t{tags}",
+                    session.id, session.messages
+                );
+            }
+        }
+        ["show", id] => {
+            for file in sessions::find(&dir, id)?.files {
+                println!("{}", file.display());
+            }
+        }
+        ["delete", id] => {
+            sessions::delete(&dir, id)?;
+            println!("Deleted {id}");
+        }
+        ["name", id, name @ ..] => {
+            let name = (!name.is_empty()).then(|| name.join(" "));
+
+            sessions::update_meta(&dir, id, |meta| meta.name = name)?;
+        }
+        ["tag", id, tags @ ..] => {
+            sessions::update_meta(&dir, id, |meta| meta.tag(tags))?;
+        }
+        ["untag", id, tags @ ..] => {
+            sessions::update_meta(&dir, id, |meta| meta.untag(tags))?;
+        }
+        ["prune"] => {
+            for id in sessions::prune_configured()? {
+                println!("Deleted {id}");
+            }
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+
+    Ok(())
+}
+
+/// Converts a recorded `.cmdir` file into a `.commands` file next to it, returning the new path
+pub fn convert_cmdir(path: &Path) -> anyhow::Result<PathBuf> {
+    let output = path.with_extension("commands");
+
+    convert_cmdir_to(path, &output)?;
+
+    Ok(output)
+}
+
+fn convert_cmdir_to(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let cmdir = std::fs::read_to_string(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?;
+
+    let script = commands::cmdir::to_script(&cmdir)?;
+
+    std::fs::write(output, script)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+
+    Ok(())
+}
+
+/// Imports a real chat log, writing both a lossless recording and a `.commands` script named after the output
+///
+/// `.json` files are read as VOD chat downloads, anything else as a raw IRC log.
+pub fn import_chat_log(input: &Path, output: &Path) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let log = std::fs::read_to_string(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?;
+
+    let events = if input.extension().is_some_and(|ext| ext == "json") {
+        twitch_api::import::from_vod_json(&log, crate::config::Config::read().channel_or_default())?
+    } else {
+        twitch_api::import::from_irc_log(&log)?
+    };
+
+    let recording = output.with_extension("jsonl");
+    let file = std::fs::File::create(&recording)
+        .with_context(|| format!("Failed to write {}", recording.display()))?;
+    twitch_api::recording::write(std::io::BufWriter::new(file), &events)?;
+
+    let script = output.with_extension("commands");
+    std::fs::write(&script, to_script(&events))
+        .with_context(|| format!("Failed to write {}", script.display()))?;
+
+    Ok((recording, script))
+}
+
+/// Exports a `.jsonl` recording into another format
+pub fn export_recording(input: &Path, format: ExportFormat, output: &Path) -> anyhow::Result<()> {
+    let file = std::fs::File::open(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?;
+    let events = twitch_api::recording::read(std::io::BufReader::new(file))?;
+
+    let exported = export::export(&events, format, &SubtitleOptions::default())?;
+
+    std::fs::write(output, exported)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+
+    Ok(())
+}
+
+/// Converts recorded events into a script, keeping the gaps between them
+///
+/// Scripts only refer to users by name, so the recording should be replayed when colors and badges matter.
+fn to_script(events: &[RecordedEvent]) -> String {
+    let mut script = String::new();
+    let mut previous = events.first().map_or(0, |event| event.elapsed);
+
+    for event in events {
+        let gap = event.elapsed.saturating_sub(previous);
+        previous = event.elapsed;
+
+        if gap > 0 {
+            script.push_str(&Command::Sleep { delay: gap }.to_string());
+            script.push('\n');
+        }
+
+        let send = Command::Send {
+            message: event.message.clone(),
+            count: 1,
+            delay: Amount::Single(0),
+            username: event.user.name.clone(),
+            channel: Some(event.channel.clone()),
+        };
+
+        script.push_str(&send.to_string());
+        script.push('\n');
+    }
+
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_cmdir() {
+        let dir = std::env::temp_dir().join(format!("fauxchat-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let input = dir.join("session.cmdir");
+        std::fs::write(
+            &input,
+            "end_pause(1000)\nsend(\"Hey!\", 1, 0, \"someone\")\nend_pause(1500)\nsend(\"Hey!\", 1, 0, \"someone\")\n",
+        )
+        .unwrap();
+
+        let output = convert_cmdir(&input).unwrap();
+
+        assert_eq!(output, dir.join("session.commands"));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "send(\"Hey!\", 1, 0, \"someone\")\nsleep(500)\nsend(\"Hey!\", 1, 0, \"someone\")\n"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_chat_log() {
+        let dir = std::env::temp_dir().join(format!("fauxchat-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let input = dir.join("stream.log");
+        std::fs::write(
+            &input,
+            "@color=#1E90FF;display-name=SomeOne;tmi-sent-ts=1000;user-id=1 :someone!someone@someone.tmi.twitch.tv PRIVMSG #fauxchat :Hello\n\
+             @color=;display-name=another;tmi-sent-ts=2500;user-id=2 :another!another@another.tmi.twitch.tv PRIVMSG #fauxchat :Hey \"you\"\n",
+        )
+        .unwrap();
+
+        let (recording, script) = import_chat_log(&input, &input).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(script).unwrap(),
+            "send(\"Hello\", 1, 0, \"SomeOne\", \"#fauxchat\")\nsleep(1500)\nsend(\"Hey \\\"you\\\"\", 1, 0, \"another\", \"#fauxchat\")\n"
+        );
+
+        let file = std::io::BufReader::new(std::fs::File::open(&recording).unwrap());
+        let events = twitch_api::recording::read(file).unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].elapsed, 1500);
+
+        let srt = dir.join("stream.srt");
+        export_recording(&recording, ExportFormat::Srt, &srt).unwrap();
+
+        assert!(std::fs::read_to_string(srt)
+            .unwrap()
+            .starts_with("1\n00:00:00,000 --> 00:00:01,500\nSomeOne: Hello\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_non_ascii_script_parses() {
+        let log = "@tmi-sent-ts=1000 :someone!someone@someone.tmi.twitch.tv PRIVMSG #fauxchat :héllo 👩\u{200d}💻 e\u{301} \u{e0041}\n\
+                   @tmi-sent-ts=2000 :another!another@another.tmi.twitch.tv PRIVMSG #fauxchat :zero\u{200b}width";
+
+        let events = twitch_api::import::from_irc_log(log).unwrap();
+        let script = to_script(&events);
+
+        let messages: Vec<_> = script
+            .lines()
+            .filter_map(|line| match Command::try_from(line.to_string()).unwrap() {
+                Command::Send { message, .. } => Some(message),
+                Command::Sleep { .. } => None,
+            })
+            .collect();
+
+        assert_eq!(
+            messages,
+            ["héllo 👩\u{200d}💻 e\u{301} \u{e0041}", "zero\u{200b}width"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_without_subcommand() {
+        assert!(run(std::iter::empty()).await.is_none());
+        assert!(run(["convert".to_string()].into_iter())
+            .await
+            .unwrap()
+            .is_err());
+    }
+}
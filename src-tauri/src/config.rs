@@ -0,0 +1,117 @@
+use std::{path::PathBuf, time::Duration};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use twitch_api::{
+    cache,
+    creds::Store,
+    randomise::{RandomiseMode, RoleRatios},
+    PoolSource,
+};
+
+use crate::sessions::Retention;
+
+pub static CONFIG: Lazy<Mutex<Config>> = Lazy::new(|| {
+    Mutex::new(Config::load().unwrap_or_else(|e| {
+        warn!("Failed to load config, using defaults: {}", e);
+        Config::default()
+    }))
+});
+
+/// The name used for the channel when none is configured, but one is needed
+pub const DEFAULT_CHANNEL: &str = "fauxchat";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The channel messages are sent to, when a command does not specify one
+    ///
+    /// If unset, they are sent to every channel a client has joined, so overlays work whatever channel they open.
+    pub channel: Option<String>,
+    /// How many recorded sessions are kept
+    pub sessions: Retention,
+    /// Where the Twitch credentials are kept, `FAUXCHAT_CREDENTIALS_STORE` or a file if unset
+    pub credentials: Option<Store>,
+    /// How the pool of chatters is built
+    pub pool: PoolConfig,
+    /// Runs without Twitch, using a pool of made up users, also enabled by setting `FAUXCHAT_OFFLINE`
+    pub offline: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Who the pool is downloaded from, `followers` or `chatters`
+    pub source: PoolSource,
+    /// How many hours the cached pool is used before it is downloaded again, where 0 downloads it every launch
+    pub ttl_hours: u64,
+    /// How many made up users are in the pool when offline
+    pub synthetic_users: usize,
+    /// How often roles are given out to made up users, or when randomising
+    pub roles: RoleRatios,
+    /// Gives out roles at random when the pool is loaded, `replace` or `noise` to keep the real ones too
+    pub randomise: Option<RandomiseMode>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            source: PoolSource::default(),
+            ttl_hours: cache::DEFAULT_TTL.as_secs() / (60 * 60),
+            synthetic_users: 500,
+            roles: RoleRatios::default(),
+            randomise: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    #[must_use]
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_hours * 60 * 60)
+    }
+}
+
+impl Config {
+    /// Clone the current config, not meant to be modified, but drops the lock
+    pub fn read() -> Config {
+        CONFIG.lock().clone()
+    }
+
+    /// The configured channel, or a stand in for places that need a name
+    pub fn channel_or_default(&self) -> &str {
+        self.channel.as_deref().unwrap_or(DEFAULT_CHANNEL)
+    }
+
+    /// Whether Twitch is left alone, as configured or through `FAUXCHAT_OFFLINE`
+    pub fn is_offline(&self) -> bool {
+        self.offline
+            || std::env::var("FAUXCHAT_OFFLINE")
+                .is_ok_and(|offline| !matches!(offline.as_str(), "" | "0" | "false"))
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::get_path()?;
+
+        if path.exists() {
+            Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn get_path() -> anyhow::Result<PathBuf> {
+        let dir = directories::ProjectDirs::from("com", "jewelexx", "FauxChat")
+            .ok_or_else(|| anyhow::anyhow!("Could not find the config directory"))?;
+
+        let config_dir = dir.config_dir();
+
+        if !config_dir.exists() {
+            std::fs::create_dir_all(config_dir)?;
+        }
+
+        Ok(config_dir.join("config.toml"))
+    }
+}
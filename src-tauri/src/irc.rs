@@ -1,114 +1,209 @@
-use std::{
-    path::PathBuf,
-    thread,
-    time::{Duration, UNIX_EPOCH},
-};
+use std::{path::PathBuf, time::UNIX_EPOCH};
 
 use actix::{prelude::*, Actor, AsyncContext, StreamHandler};
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 
-use commands::Command;
-use crossbeam::channel::Receiver;
+use commands::{amount::Amount, Command};
 use parking_lot::Mutex;
-use twitch_api::TwitchUser;
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+use twitch_api::{irc::IrcMessage, recording::Recorder, TwitchUser};
+
+use crate::scheduler::{Control, Emission, Scheduler};
+
+use session::Session;
+
+mod session;
+pub mod tcp;
 
 #[allow(clippy::unused_async, clippy::needless_pass_by_value)]
 pub async fn handle_ws(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-    let resp = ws::start(FakeIrc, &req, stream);
+    let resp = ws::start(FakeIrc::default(), &req, stream);
     dbg!(resp)
 }
 
-pub static RECIPIENTS: Mutex<Vec<Recipient<Message>>> = Mutex::new(Vec::new());
+/// A connected IRC client, over any of the supported transports
+#[derive(Clone)]
+pub enum Connection {
+    WebSocket(Recipient<Message>),
+    Tcp(UnboundedSender<Message>),
+}
 
-pub fn send_messages(receiver: &Receiver<Command>, path: PathBuf) {
-    use std::{fs::OpenOptions, io::Write};
+impl Connection {
+    pub fn send(&self, msg: Message) {
+        match self {
+            Self::WebSocket(recipient) => recipient.do_send(msg),
+            // The receiver is only dropped once the connection is closing, so the message is irrelevant
+            Self::Tcp(sender) => _ = sender.send(msg),
+        }
+    }
+}
+
+impl PartialEq for Connection {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::WebSocket(a), Self::WebSocket(b)) => a == b,
+            (Self::Tcp(a), Self::Tcp(b)) => a.same_channel(b),
+            _ => false,
+        }
+    }
+}
+
+pub static RECIPIENTS: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+
+fn remove_recipient(conn: &Connection) {
+    let mut recipients = RECIPIENTS.lock();
+
+    if let Some(index) = recipients.iter().position(|recipient| recipient == conn) {
+        recipients.remove(index);
+    }
+}
+
+pub async fn send_messages(mut receiver: UnboundedReceiver<Control>, path: PathBuf) {
+    use std::fs::OpenOptions;
 
     let mut file = OpenOptions::new()
-        .write(true)
         .append(true)
         .create(true)
-        .open(path)
+        .open(&path)
         .unwrap();
 
-    // TODO: Maybe for longer running tasks, run it on a separate thread, so other messages can come in
-
-    // While loop will exit once connection is closed
-    while let Ok(cmd) = receiver.recv() {
-        println!("Found a message");
-        // Skip any comments or empty lines
-
-        debug!("Sending message");
-
-        debug!("{:?}", cmd);
-
-        let command_string = cmd.to_string();
-
-        // Uses milliseconds as some commands might be sent in quick succession
-        let now = std::time::SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-
-        // TODO: Add username to sent command
-        writeln!(file, "end_pause({now})").unwrap();
-
-        match cmd {
-            Command::Send {
-                ref message,
-                ref username,
-                count,
-                delay,
-            } => {
-                let conns = RECIPIENTS.lock().len();
-                debug!("{conns} connections");
-                debug!("Sending message");
-
-                for _ in 0..count {
-                    let user = {
-                        if username == "random" {
-                            TwitchUser::random()
-                        } else {
-                            TwitchUser::from_username(username)
-                        }
-                    };
+    let mut recorder = Recorder::new(
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path.with_extension("jsonl"))
+            .unwrap(),
+    );
 
-                    let delay = Duration::from_millis(delay.get_value());
+    let mut scheduler = Scheduler::default();
 
-                    writeln!(file, "send(\"{message}\", 1, 0, \"{}\")", user.name).unwrap();
+    // Loop will exit once connection is closed
+    loop {
+        let next_due = scheduler.next_due();
 
-                    writeln!(file, "sleep({})", delay.as_millis()).unwrap();
+        tokio::select! {
+            control = receiver.recv() => {
+                let Some(control) = control else { break };
+                let now = Instant::now();
 
-                    let parsed = user.send_message(message);
+                debug!("Received {:?}", control);
 
-                    for conn in RECIPIENTS.lock().iter() {
-                        conn.do_send(Message(parsed.clone()));
+                match control {
+                    Control::Schedule(cmd) => {
+                        let job = scheduler.schedule(&cmd, now);
+                        debug!("Scheduled job {job}, {} messages pending", scheduler.len());
                     }
-
-                    debug!("Sleeping for {} milliseconds", delay.as_millis());
-
-                    thread::sleep(delay);
+                    Control::Replay(messages) => {
+                        let job = scheduler.replay(messages, now);
+                        debug!("Scheduled replay {job}, {} messages pending", scheduler.len());
+                    }
+                    Control::Pause => scheduler.pause(now),
+                    Control::Resume => scheduler.resume(now),
+                    Control::Skip => scheduler.skip(now),
+                    Control::Cancel(job) => {
+                        if !scheduler.cancel(job, now) {
+                            warn!("Could not cancel job {job}, as it is not pending");
+                        }
+                    }
+                    Control::Clear => scheduler.clear(),
+                    // The requester may have given up waiting for a reply, which is fine
+                    Control::Status(reply) => _ = reply.send(scheduler.status(now)),
+                    Control::SetSpeed(speed, reply) => _ = reply.send(scheduler.set_speed(speed, now)),
                 }
             }
-            Command::Sleep { delay } => {
-                let delay = Duration::from_millis(delay);
-                writeln!(file, "{command_string}").unwrap();
-                thread::sleep(delay);
+            () = sleep_until_due(next_due) => {
+                for emission in scheduler.pop_due(Instant::now()) {
+                    emit(&emission, &mut file, &mut recorder);
+                }
             }
         }
-
-        debug!("Message sent");
     }
+
     error!("Done sending messages");
 }
 
+/// Waits until the given instant, or forever if there is nothing to wait for
+async fn sleep_until_due(due: Option<Instant>) {
+    match due {
+        Some(due) => tokio::time::sleep_until(due).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends a single message to every connected client, recording it unless it is being replayed
+fn emit(
+    emission: &Emission,
+    file: &mut impl std::io::Write,
+    recorder: &mut Recorder<impl std::io::Write>,
+) {
+    let (message, username, requested) = match emission {
+        Emission::Send {
+            message,
+            username,
+            channel,
+        } => (message, username, channel),
+        Emission::Replay(msg) => return broadcast(msg),
+    };
+
+    let user = if username == "random" {
+        TwitchUser::random()
+    } else {
+        TwitchUser::from_username(username)
+    };
+
+    let channel = requested
+        .clone()
+        .or_else(|| crate::config::Config::read().channel)
+        .unwrap_or_else(|| session::ANY_CHANNEL.to_string());
+
+    // Uses milliseconds as some commands might be sent in quick succession
+    let now = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    writeln!(file, "end_pause({now})").unwrap();
+    let command = Command::Send {
+        message: message.clone(),
+        count: 1,
+        delay: Amount::Single(0),
+        username: user.name.clone(),
+        channel: requested.clone(),
+    };
+    writeln!(file, "{command}").unwrap();
+
+    let parsed = user.privmsg(&channel, message);
+
+    if let Err(e) = recorder.record(&user, &parsed) {
+        error!("Failed to record message: {}", e);
+    }
+
+    broadcast(&parsed);
+}
+
+fn broadcast(msg: &IrcMessage) {
+    let recipients = RECIPIENTS.lock();
+    debug!("Sending message to {} connections", recipients.len());
+
+    for conn in recipients.iter() {
+        conn.send(Message(msg.clone()));
+    }
+}
+
 /// Chat server sends this messages to session
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Message(pub String);
+pub struct Message(pub IrcMessage);
 
 #[allow(clippy::module_name_repetitions)]
-pub struct FakeIrc;
+#[derive(Default)]
+pub struct FakeIrc {
+    session: Session,
+}
 
 // TODO: Add Heartbeats
 
@@ -116,7 +211,9 @@ impl actix::Handler<Message> for FakeIrc {
     type Result = ();
 
     fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.0);
+        for msg in self.session.deliver(&msg.0) {
+            ctx.text(msg.to_string());
+        }
     }
 }
 
@@ -124,18 +221,13 @@ impl Actor for FakeIrc {
     type Context = ws::WebsocketContext<Self>;
 
     fn stopped(&mut self, ctx: &mut Self::Context) {
-        let mut recipients = RECIPIENTS.lock();
-        let index = recipients
-            .iter()
-            .position(|addr| addr == &ctx.address().recipient());
-
-        if let Some(index) = index {
-            recipients.remove(index);
-        }
+        remove_recipient(&Connection::WebSocket(ctx.address().recipient()));
     }
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        RECIPIENTS.lock().push(ctx.address().recipient::<Message>());
+        RECIPIENTS
+            .lock()
+            .push(Connection::WebSocket(ctx.address().recipient()));
 
         info!("Creating message sender interval");
 
@@ -179,7 +271,7 @@ impl Actor for FakeIrc {
         //                         }
         //                     };
 
-        //                     let parsed = user.send_message(message);
+        //                     let parsed = user.privmsg(&channel, message);
         //                     ctx.text(parsed);
 
         //                     debug!("Sleeping for {} milliseconds", delay.as_millis());
@@ -203,7 +295,19 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for FakeIrc {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                info!("Received: {}", text);
+                // Clients may send several lines in a single frame
+                for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                    match line.parse::<IrcMessage>() {
+                        Ok(parsed) => {
+                            info!("Received: {:?}", parsed);
+
+                            for reply in self.session.handle(&parsed) {
+                                ctx.text(reply.to_string());
+                            }
+                        }
+                        Err(e) => warn!("Received invalid IRC message {:?}: {}", line, e),
+                    }
+                }
             }
             _ => (),
         }
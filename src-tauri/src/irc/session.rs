@@ -0,0 +1,416 @@
+use twitch_api::irc::{channel_name, IrcMessage};
+
+/// The hostname Twitch uses as the prefix for server messages
+pub const SERVER: &str = "tmi.twitch.tv";
+
+/// Messages sent to this channel go to every channel a client has joined, when no channel is configured
+pub const ANY_CHANNEL: &str = "#*";
+
+/// Capabilities Twitch will acknowledge if requested
+const CAPABILITIES: [&str; 3] = [
+    "twitch.tv/tags",
+    "twitch.tv/commands",
+    "twitch.tv/membership",
+];
+
+/// The state of a single client connection, independent of the transport it arrived on
+///
+/// Mirrors the replies Twitch gives during the IRC handshake, closely enough that standard client libraries connect unmodified
+#[derive(Debug, Default)]
+pub struct Session {
+    nick: Option<String>,
+    capabilities: Vec<String>,
+    channels: Vec<String>,
+}
+
+impl Session {
+    /// Whether the client has joined the given channel
+    #[must_use]
+    pub fn is_joined(&self, channel: &str) -> bool {
+        self.channels.iter().any(|joined| joined == channel)
+    }
+
+    /// Whether a message broadcast by the server should be delivered to this client
+    ///
+    /// Real Twitch only sends chat for channels a client has joined
+    #[must_use]
+    pub fn should_receive(&self, msg: &IrcMessage) -> bool {
+        msg.params.first().is_some_and(|channel| {
+            self.is_joined(channel) || (channel == ANY_CHANNEL && !self.channels.is_empty())
+        })
+    }
+
+    /// The messages to send the client for a message broadcast by the server
+    ///
+    /// Those sent to [`ANY_CHANNEL`] are sent once for each channel the client has joined.
+    #[must_use]
+    pub fn deliver(&self, msg: &IrcMessage) -> Vec<IrcMessage> {
+        if !self.should_receive(msg) {
+            return Vec::new();
+        }
+
+        if msg
+            .params
+            .first()
+            .is_some_and(|channel| channel == ANY_CHANNEL)
+        {
+            self.channels
+                .iter()
+                .map(|channel| {
+                    let mut msg = msg.clone();
+                    msg.params[0].clone_from(channel);
+
+                    self.tagged(msg)
+                })
+                .collect()
+        } else {
+            vec![self.tagged(msg.clone())]
+        }
+    }
+
+    fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|cap| cap == capability)
+    }
+
+    fn nick(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+
+    /// Creates a numeric reply addressed to the client
+    fn numeric(&self, code: &str, text: &str) -> IrcMessage {
+        IrcMessage::new(code)
+            .prefix(SERVER)
+            .param(self.nick())
+            .param(text)
+    }
+
+    /// Handles a message sent by the client, returning the replies that should be sent back
+    pub fn handle(&mut self, msg: &IrcMessage) -> Vec<IrcMessage> {
+        match msg.command.to_uppercase().as_str() {
+            "CAP" => self.handle_cap(msg),
+            "NICK" => self.handle_nick(msg),
+            "JOIN" => self.handle_join(msg),
+            "PART" => self.handle_part(msg),
+            "PING" => vec![IrcMessage::new("PONG")
+                .prefix(SERVER)
+                .param(SERVER)
+                .param(msg.params.first().map_or(SERVER, String::as_str))],
+            // Any token is accepted, as there is nothing to authenticate against,
+            // and Twitch does not echo a client's own messages back to it
+            "PASS" | "PRIVMSG" | "PONG" => Vec::new(),
+            command => {
+                warn!("Received unsupported IRC command: {}", command);
+
+                vec![IrcMessage::new("421")
+                    .prefix(SERVER)
+                    .param(self.nick())
+                    .param(command)
+                    .param("Unknown command")]
+            }
+        }
+    }
+
+    fn handle_cap(&mut self, msg: &IrcMessage) -> Vec<IrcMessage> {
+        let subcommand = msg.params.first().map(|sub| sub.to_uppercase());
+
+        match subcommand.as_deref() {
+            Some("LS") => vec![IrcMessage::new("CAP")
+                .prefix(SERVER)
+                .param("*")
+                .param("LS")
+                .param(CAPABILITIES.join(" "))],
+            Some("REQ") => {
+                let requested = msg.params.get(1).map_or("", String::as_str);
+
+                let supported = requested
+                    .split_whitespace()
+                    .all(|cap| CAPABILITIES.contains(&cap));
+
+                // Twitch either acknowledges all requested capabilities or none of them
+                if supported {
+                    for cap in requested.split_whitespace() {
+                        if !self.has_capability(cap) {
+                            self.capabilities.push(cap.to_string());
+                        }
+                    }
+                }
+
+                vec![IrcMessage::new("CAP")
+                    .prefix(SERVER)
+                    .param("*")
+                    .param(if supported { "ACK" } else { "NAK" })
+                    .param(requested)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn handle_nick(&mut self, msg: &IrcMessage) -> Vec<IrcMessage> {
+        let Some(nick) = msg.params.first() else {
+            return vec![self.numeric("431", "No nickname given")];
+        };
+
+        self.nick = Some(nick.to_lowercase());
+
+        let mut replies = vec![
+            self.numeric("001", "Welcome, GLHF!"),
+            self.numeric("002", "Your host is tmi.twitch.tv"),
+            self.numeric("003", "This server is rather new"),
+            self.numeric("004", "-"),
+            self.numeric("375", "-"),
+            self.numeric("372", "You are in a maze of twisty passages, all alike."),
+            self.numeric("376", ">"),
+        ];
+
+        // Anonymous users never receive a GLOBALUSERSTATE
+        if self.has_capability("twitch.tv/commands") && !self.nick().starts_with("justinfan") {
+            replies.push(
+                self.tagged(
+                    IrcMessage::new("GLOBALUSERSTATE")
+                        .tag("badge-info", "")
+                        .tag("badges", "")
+                        .tag("color", "")
+                        .tag("display-name", self.nick())
+                        .tag("emote-sets", "0")
+                        .tag("user-id", "0")
+                        .tag("user-type", "")
+                        .prefix(SERVER),
+                ),
+            );
+        }
+
+        replies
+    }
+
+    fn handle_join(&mut self, msg: &IrcMessage) -> Vec<IrcMessage> {
+        let Some(channels) = msg.params.first() else {
+            return Vec::new();
+        };
+
+        let nick = self.nick().to_string();
+
+        let mut replies = Vec::new();
+
+        for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+            let channel = channel_name(channel);
+
+            replies.push(
+                IrcMessage::new("JOIN")
+                    .prefix(IrcMessage::user_prefix(&nick))
+                    .param(&channel),
+            );
+
+            replies.push(
+                IrcMessage::new("353")
+                    .prefix(format!("{nick}.{SERVER}"))
+                    .param(&nick)
+                    .param("=")
+                    .param(&channel)
+                    .param(&nick),
+            );
+
+            replies.push(
+                IrcMessage::new("366")
+                    .prefix(format!("{nick}.{SERVER}"))
+                    .param(&nick)
+                    .param(&channel)
+                    .param("End of /NAMES list"),
+            );
+
+            if self.has_capability("twitch.tv/commands") {
+                replies.push(
+                    self.tagged(
+                        IrcMessage::new("USERSTATE")
+                            .tag("badge-info", "")
+                            .tag("badges", "")
+                            .tag("color", "")
+                            .tag("display-name", &nick)
+                            .tag("emote-sets", "0")
+                            .tag("mod", "0")
+                            .tag("subscriber", "0")
+                            .tag("user-type", "")
+                            .prefix(SERVER)
+                            .param(&channel),
+                    ),
+                );
+
+                let room_id = twitch_api::creds::Credentials::read().user_id;
+
+                replies.push(
+                    self.tagged(
+                        IrcMessage::new("ROOMSTATE")
+                            .tag("emote-only", "0")
+                            .tag("followers-only", "-1")
+                            .tag("r9k", "0")
+                            .tag("room-id", &room_id)
+                            .tag("slow", "0")
+                            .tag("subs-only", "0")
+                            .prefix(SERVER)
+                            .param(&channel),
+                    ),
+                );
+            }
+
+            if !self.channels.contains(&channel) {
+                self.channels.push(channel);
+            }
+        }
+
+        replies
+    }
+
+    fn handle_part(&mut self, msg: &IrcMessage) -> Vec<IrcMessage> {
+        let Some(channels) = msg.params.first() else {
+            return Vec::new();
+        };
+
+        let nick = self.nick().to_string();
+
+        channels
+            .split(',')
+            .map(channel_name)
+            .filter_map(|channel| {
+                let index = self.channels.iter().position(|joined| joined == &channel)?;
+                self.channels.remove(index);
+
+                Some(
+                    IrcMessage::new("PART")
+                        .prefix(IrcMessage::user_prefix(&nick))
+                        .param(channel),
+                )
+            })
+            .collect()
+    }
+
+    /// Strips the tags from a message if the client did not request them
+    #[must_use]
+    pub fn tagged(&self, mut msg: IrcMessage) -> IrcMessage {
+        if !self.has_capability("twitch.tv/tags") {
+            msg.tags = twitch_api::irc::Tags::new();
+        }
+
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(session: &mut Session, line: &str) -> Vec<String> {
+        session
+            .handle(&line.parse().unwrap())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_cap_req() {
+        let mut session = Session::default();
+
+        let replies = send(&mut session, "CAP REQ :twitch.tv/tags twitch.tv/membership");
+        assert_eq!(
+            replies,
+            [":tmi.twitch.tv CAP * ACK :twitch.tv/tags twitch.tv/membership"]
+        );
+
+        let replies = send(&mut session, "CAP REQ :twitch.tv/tags some/other");
+        assert_eq!(
+            replies,
+            [":tmi.twitch.tv CAP * NAK :twitch.tv/tags some/other"]
+        );
+    }
+
+    #[test]
+    fn test_welcome() {
+        let mut session = Session::default();
+
+        assert!(send(&mut session, "PASS SCHMOOPIIE").is_empty());
+
+        let replies = send(&mut session, "NICK justinfan123");
+        let codes: Vec<&str> = replies
+            .iter()
+            .map(|reply| reply.split(' ').nth(1).unwrap())
+            .collect();
+
+        assert_eq!(codes, ["001", "002", "003", "004", "375", "372", "376"]);
+        assert_eq!(
+            replies[0],
+            ":tmi.twitch.tv 001 justinfan123 :Welcome, GLHF!"
+        );
+    }
+
+    #[test]
+    fn test_join_and_part() {
+        let mut session = Session::default();
+        send(&mut session, "NICK justinfan123");
+
+        assert!(!session.is_joined("#somechannel"));
+
+        let replies = send(&mut session, "JOIN #SomeChannel");
+        assert_eq!(
+            replies,
+            [
+                ":justinfan123!justinfan123@justinfan123.tmi.twitch.tv JOIN #somechannel",
+                ":justinfan123.tmi.twitch.tv 353 justinfan123 = #somechannel :justinfan123",
+                ":justinfan123.tmi.twitch.tv 366 justinfan123 #somechannel :End of /NAMES list",
+            ]
+        );
+        assert!(session.is_joined("#somechannel"));
+
+        let replies = send(&mut session, "PART #somechannel");
+        assert_eq!(
+            replies,
+            [":justinfan123!justinfan123@justinfan123.tmi.twitch.tv PART #somechannel"]
+        );
+        assert!(!session.is_joined("#somechannel"));
+    }
+
+    #[test]
+    fn test_multiple_channels() {
+        let mut session = Session::default();
+        send(&mut session, "NICK justinfan123");
+        send(&mut session, "JOIN #first,#second");
+
+        let privmsg = |channel: &str| IrcMessage::new("PRIVMSG").param(channel).param("Hi!");
+
+        assert!(session.should_receive(&privmsg("#first")));
+        assert!(session.should_receive(&privmsg("#second")));
+        assert!(!session.should_receive(&privmsg("#third")));
+
+        send(&mut session, "PART #first");
+
+        assert!(!session.should_receive(&privmsg("#first")));
+        assert!(session.should_receive(&privmsg("#second")));
+    }
+
+    #[test]
+    fn test_any_channel() {
+        let mut session = Session::default();
+        send(&mut session, "NICK justinfan123");
+
+        let privmsg = IrcMessage::new("PRIVMSG").param(ANY_CHANNEL).param("Hi!");
+
+        assert!(session.deliver(&privmsg).is_empty());
+
+        send(&mut session, "JOIN #first,#second");
+
+        let delivered: Vec<_> = session
+            .deliver(&privmsg)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(delivered, ["PRIVMSG #first :Hi!", "PRIVMSG #second :Hi!"]);
+    }
+
+    #[test]
+    fn test_ping() {
+        let mut session = Session::default();
+
+        assert_eq!(
+            send(&mut session, "PING :tmi.twitch.tv"),
+            [":tmi.twitch.tv PONG tmi.twitch.tv :tmi.twitch.tv"]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{fs::File, io, path::Path, sync::Arc};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc::unbounded_channel,
+};
+use tokio_rustls::{rustls, TlsAcceptor};
+use twitch_api::irc::IrcMessage;
+
+use super::{remove_recipient, Connection, Message, Session, RECIPIENTS};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Failed to read certificate or key: {0}")]
+    Io(#[from] io::Error),
+    #[error("No private key found in {0}")]
+    MissingKey(String),
+    #[error("Invalid certificate or key: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Creates a TLS acceptor from PEM encoded certificate and private key files
+pub fn tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, TlsError> {
+    let certs = rustls_pemfile::certs(&mut io::BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    let key = rustls_pemfile::read_all(&mut io::BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::MissingKey(key_path.display().to_string()))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts raw IRC connections, as a bot would make to `irc.chat.twitch.tv:6667`
+///
+/// Clients share the same broadcast and handshake as those connected over WebSocket
+pub async fn listen(listener: TcpListener, tls: Option<TlsAcceptor>) -> io::Result<()> {
+    info!(
+        "Listening for IRC connections on {}",
+        listener.local_addr()?
+    );
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let tls = tls.clone();
+
+        debug!("Accepted IRC connection from {}", peer);
+
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => handle_connection(stream).await,
+                    Err(e) => Err(e),
+                },
+                None => handle_connection(stream).await,
+            };
+
+            if let Err(e) = result {
+                warn!("IRC connection from {} closed with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn write_message(writer: &mut (impl AsyncWrite + Unpin), msg: &IrcMessage) -> io::Result<()> {
+    writer.write_all(format!("{msg}\r\n").as_bytes()).await
+}
+
+async fn handle_connection(stream: impl AsyncRead + AsyncWrite + Unpin) -> io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    let (tx, mut rx) = unbounded_channel();
+    let conn = Connection::Tcp(tx);
+
+    RECIPIENTS.lock().push(conn.clone());
+
+    let mut session = Session::default();
+
+    let result = async {
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    // The connection was closed by the client
+                    let Some(line) = line? else { break Ok(()) };
+
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    match line.parse::<IrcMessage>() {
+                        Ok(parsed) => {
+                            info!("Received: {:?}", parsed);
+
+                            for reply in session.handle(&parsed) {
+                                write_message(&mut writer, &reply).await?;
+                            }
+                        }
+                        Err(e) => warn!("Received invalid IRC message {:?}: {}", line, e),
+                    }
+                }
+                Some(Message(msg)) = rx.recv() => {
+                    for msg in session.deliver(&msg) {
+                        write_message(&mut writer, &msg).await?;
+                    }
+                }
+            }
+        }
+    }
+    .await;
+
+    remove_recipient(&conn);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tcp_handshake() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(listen(listener, None));
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(b"NICK justinfan123\r\n").await?;
+
+        let mut lines = BufReader::new(stream).lines();
+
+        assert_eq!(
+            lines.next_line().await?.as_deref(),
+            Some(":tmi.twitch.tv 001 justinfan123 :Welcome, GLHF!")
+        );
+
+        Ok(())
+    }
+}
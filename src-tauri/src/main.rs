@@ -3,44 +3,109 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::unsafe_derive_deserialize, clippy::missing_errors_doc)]
 
-use std::{path::PathBuf, sync::Arc};
+use std::sync::Arc;
 
-use actix_web::{web, App, HttpServer};
+use actix_web::{App, HttpServer};
 use commands::Command;
-use crossbeam::channel::{unbounded, Sender};
 use once_cell::sync::OnceCell;
-use time::macros::format_description;
-use tokio::{fs::File, io::AsyncReadExt};
+use scheduler::Control;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tracing_subscriber::fmt::format::FmtSpan;
 
 use twitch_api::creds::Credentials;
 
+mod cli;
+mod config;
 mod irc;
 mod net;
+mod pool;
 mod routes;
+mod scheduler;
+mod sessions;
 
 #[macro_use]
 extern crate tracing;
 
-static mut TX: OnceCell<Sender<Command>> = OnceCell::new();
+static mut TX: OnceCell<UnboundedSender<Control>> = OnceCell::new();
 
-// #[cfg(not(debug_assertions))]
-fn cmdir_dir() -> PathBuf {
-    directories::ProjectDirs::from("com", "jewelexx", "FauxChat")
-        .unwrap()
-        .cache_dir()
-        .to_path_buf()
-}
+fn send_control(control: Control) {
+    let tx = unsafe { TX.wait() };
 
-// #[cfg(debug_assertions)]
-// fn cmdir_dir() -> PathBuf {
-//     PathBuf::new()
-// }
+    tx.send(control)
+        .expect("connected channel. receiver dropped?");
+}
 
 fn ready_message(msg: Command) {
-    let tx = unsafe { TX.wait() };
+    send_control(Control::Schedule(msg));
+}
+
+/// Fetches the pending jobs from the scheduler, or [`None`] if it has stopped
+async fn queue_status() -> Option<scheduler::QueueStatus> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    send_control(Control::Status(tx));
+
+    rx.await.ok()
+}
+
+/// Changes the playback speed of the scheduler, including anything already queued, or [`None`] if it has stopped
+async fn set_speed(speed: f64) -> Option<Result<(), commands::speed::SpeedError>> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    send_control(Control::SetSpeed(speed, tx));
+
+    rx.await.ok()
+}
+
+/// Uses the credentials store from the config, if it sets one
+fn select_credentials_store() {
+    if let Some(store) = config::Config::read().credentials {
+        store.select();
+    }
+}
+
+/// Loads the credentials and keeps them fresh, exiting with instructions on how to set them up if that fails
+///
+/// Offline there is nothing to load, only the broadcaster is made up.
+async fn init_credentials() {
+    if config::Config::read().is_offline() {
+        *twitch_api::creds::CREDENTIALS.lock() = Credentials::offline();
+        return;
+    }
+
+    select_credentials_store();
+
+    if let Err(e) = Credentials::init().await {
+        #[cfg(not(debug_assertions))]
+        tauri::api::dialog::blocking::message::<tauri::Wry>(
+            None,
+            "Missing Twitch Credentials",
+            e.to_string(),
+        );
 
-    tx.send(msg).expect("connected channel. receiver dropped?");
+        eprintln!("{e:?}");
+
+        std::process::exit(1);
+    }
+
+    twitch_api::token::CLIENT.spawn_refresh(twitch_api::token::VALIDATE_INTERVAL);
+}
+
+fn clean_up_sessions() {
+    match sessions::prune_configured() {
+        Ok(removed) if !removed.is_empty() => info!("Removed old sessions: {:?}", removed),
+        Ok(_) => (),
+        Err(e) => warn!("Failed to clean up old sessions: {}", e),
+    }
+}
+
+/// Saves the session once nothing more will be sent
+fn finish_session(cmdir_path: &std::path::Path) {
+    match sessions::finish(cmdir_path) {
+        Ok(Some(path)) => info!("Saved session to {}", path.display()),
+        Ok(None) => info!("Nothing was sent, so the session was not kept"),
+        Err(e) => error!("Failed to convert {}: {:?}", cmdir_path.display(), e),
+    }
 }
 
 mod tcmds;
@@ -52,6 +117,10 @@ async fn main() -> anyhow::Result<()> {
         .with_max_level(tracing::Level::DEBUG)
         .init();
 
+    if let Some(result) = cli::run(std::env::args().skip(1)).await {
+        return result;
+    }
+
     let mut lock = lock::Lock::init()?;
     let guard = Arc::new(lock.try_lock());
 
@@ -68,20 +137,9 @@ async fn main() -> anyhow::Result<()> {
         std::process::exit(1);
     }
 
-    Credentials::init().await?;
+    init_credentials().await;
 
-    // Must be initialized after credentials
-    once_cell::sync::Lazy::force(&twitch_api::CLIENT);
-
-    let pool = if PathBuf::from("pool.json").exists() {
-        println!("Using local user pool");
-        let mut file = File::open("pool.json").await?;
-        let mut file_str = String::new();
-        file.read_to_string(&mut file_str).await?;
-        serde_json::from_str(&file_str)?
-    } else {
-        twitch_api::UserPool::get().await?
-    };
+    let pool = pool::load().await?;
 
     trace!("Created pool");
 
@@ -91,10 +149,7 @@ async fn main() -> anyhow::Result<()> {
 
     let fut = HttpServer::new(|| {
         trace!("Creating app");
-        App::new()
-            .service(routes::twitch)
-            .service(routes::credentials)
-            .route("/ws/", web::get().to(irc::handle_ws))
+        App::new().configure(routes::configure)
     })
     .bind(net::addr())
     .expect("valid url and successful binding")
@@ -104,47 +159,65 @@ async fn main() -> anyhow::Result<()> {
         fut.await.expect("valid running of http server");
     });
 
-    let (tx, rx) = unbounded();
-
-    unsafe { TX.set(tx) }.unwrap();
-
-    let cmdir_path = {
-        let folder = cmdir_dir();
+    let irc_thread = if let Some(addr) = net::irc_addr() {
+        let tls = net::irc_tls()
+            .map(|(cert, key)| irc::tcp::tls_acceptor(&cert, &key))
+            .transpose()?;
 
-        std::fs::create_dir_all(&folder).expect("created cmdir directory");
+        let listener = tokio::net::TcpListener::bind(addr).await?;
 
-        let file_name = {
-            let now: time::OffsetDateTime = std::time::SystemTime::now().into();
+        Some(tokio::spawn(async move {
+            irc::tcp::listen(listener, tls)
+                .await
+                .expect("valid running of irc listener");
+        }))
+    } else {
+        None
+    };
 
-            let formatted_date = now
-                .format(format_description!(
-                    "[year]-[month]-[day]-[hour]-[minute]-[second]"
-                ))
-                .unwrap();
+    let (tx, rx) = unbounded_channel();
 
-            // Save as .cmdir file (short for command intermediate representation)
-            // This file will require parsing to have the "end_pause" converted into regular sleep commands
-            formatted_date + ".cmdir"
-        };
+    unsafe { TX.set(tx) }.unwrap();
 
-        folder.join(file_name)
-    };
+    // Clean up after previous launches, before this session is started
+    clean_up_sessions();
+    let cmdir_path = sessions::new_path();
 
     let messages_thread = {
         let path = cmdir_path.clone();
-        tokio::spawn(async move {
-            irc::send_messages(&rx, path);
-        })
+        tokio::spawn(irc::send_messages(rx, path))
     };
 
-    // TODO: Parse cmdir into .commands
-
     trace!("Running app");
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             tcmds::send_message,
             tcmds::invoke_command,
-            tcmds::load_file
+            tcmds::load_file,
+            tcmds::replay_recording,
+            tcmds::export_recording,
+            tcmds::list_sessions,
+            tcmds::open_session,
+            tcmds::delete_session,
+            tcmds::name_session,
+            tcmds::tag_session,
+            tcmds::untag_session,
+            tcmds::prune_sessions,
+            tcmds::pause_queue,
+            tcmds::resume_queue,
+            tcmds::skip_sleep,
+            tcmds::cancel_job,
+            tcmds::clear_queue,
+            tcmds::queue_status,
+            tcmds::set_speed,
+            tcmds::refresh_pool,
+            tcmds::randomise_pool,
+            tcmds::list_users,
+            tcmds::get_user,
+            tcmds::add_user,
+            tcmds::edit_user,
+            tcmds::delete_user,
+            tcmds::import_users,
         ])
         // .setup(|app| {
         //     let window = app.get_window("main").unwrap();
@@ -156,6 +229,9 @@ async fn main() -> anyhow::Result<()> {
 
     // Close the server when the app is closed
     server_thread.abort();
+    if let Some(irc_thread) = irc_thread {
+        irc_thread.abort();
+    }
     trace!("Server closed");
 
     // Drop the sender, thus closing the channel
@@ -165,7 +241,7 @@ async fn main() -> anyhow::Result<()> {
     messages_thread.await?;
     trace!("Messages thread completed");
 
-    // TODO: Parse cmdir into regular cmd file
+    finish_session(&cmdir_path);
 
     Ok(())
 }
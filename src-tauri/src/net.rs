@@ -15,3 +15,24 @@ pub fn port() -> u16 {
 pub fn addr() -> std::net::SocketAddr {
     std::net::SocketAddr::V4(SocketAddrV4::new(url(), port()))
 }
+
+/// The port to accept plain IRC connections on, if the TCP listener is enabled
+#[must_use]
+pub fn irc_port() -> Option<u16> {
+    let port_var = std::env::var("FAUXCHAT_IRC_PORT").ok()?;
+    Some(port_var.parse().expect("valid irc port string"))
+}
+
+#[must_use]
+pub fn irc_addr() -> Option<std::net::SocketAddr> {
+    irc_port().map(|port| std::net::SocketAddr::V4(SocketAddrV4::new(url(), port)))
+}
+
+/// The certificate and key paths to serve IRC over TLS with, if both are set
+#[must_use]
+pub fn irc_tls() -> Option<(std::path::PathBuf, std::path::PathBuf)> {
+    let cert = std::env::var_os("FAUXCHAT_IRC_TLS_CERT")?;
+    let key = std::env::var_os("FAUXCHAT_IRC_TLS_KEY")?;
+
+    Some((cert.into(), key.into()))
+}
@@ -0,0 +1,289 @@
+//! The pool of chatters messages are sent as, and the changes made to it while the app runs
+//!
+//! Edits are saved where the pool came from, with the roles it had before they were randomised. A `pool.json` in the
+//! working directory is saved as it is, while the pool cached in the data dir keeps edits apart from the download, so
+//! they are made again once it has been downloaded again. Made up pools are made again on every launch, so edits to
+//! them are not saved.
+
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use twitch_api::{
+    cache::{self, CachedPool, PoolCacheError, PoolDiff},
+    creds::Credentials,
+    edit::{PoolEdit, PoolEditError, UserPatch},
+    helix::Helix,
+    import::pool::{PoolImport, PoolImportError},
+    randomise::RandomiseMode,
+    TwitchUser, UserPool, USERS,
+};
+
+use crate::config::Config;
+
+/// The pool in the working directory, which is used over the cache when it exists, and edited in place
+pub const LOCAL_POOL: &str = "pool.json";
+
+/// Where the pool in use came from, or [`None`] if it was made up
+static SAVED: Mutex<Option<Saved>> = Mutex::new(None);
+
+#[derive(Debug, thiserror::Error)]
+pub enum PoolError {
+    #[error(transparent)]
+    Cache(#[from] PoolCacheError),
+    #[error(transparent)]
+    Edit(#[from] PoolEditError),
+    #[error(transparent)]
+    Import(#[from] PoolImportError),
+    #[error("Failed to save the pool: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to save the pool: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("The pool is loaded from {LOCAL_POOL} in the working directory, remove it to download the pool")]
+    LocalPool,
+}
+
+/// The pool in use as it is saved, before its roles were randomised
+enum Saved {
+    /// `pool.json` in the working directory
+    Local(UserPool),
+    /// The pool cached in the data dir, along with the edits made to it
+    Cached(CachedPool),
+}
+
+impl Saved {
+    /// Saves the edits, which have already been made to the pool in use
+    fn record(&mut self, edits: Vec<PoolEdit>) -> Result<(), PoolError> {
+        match self {
+            Self::Local(pool) => {
+                for edit in &edits {
+                    if let Err(e) = pool.apply(edit) {
+                        warn!("Not saving a pool edit: {e}");
+                    }
+                }
+
+                std::fs::write(LOCAL_POOL, serde_json::to_string(pool)?)?;
+            }
+            Self::Cached(cached) => {
+                cached.edits.extend(edits);
+                cached.save(&cache::dir()?)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Uses `pool.json` in the working directory if there is one, otherwise the pool cached in the data dir,
+/// downloading it again once it is older than the configured TTL
+///
+/// Roles are then randomised, if the config asks for it.
+pub async fn load() -> Result<UserPool, PoolCacheError> {
+    let config = Config::read();
+    let local = Path::new(LOCAL_POOL);
+
+    if config.is_offline() && !local.exists() {
+        info!(
+            "Offline, using {} made up users",
+            config.pool.synthetic_users
+        );
+        return Ok(UserPool::synthetic(
+            config.channel_or_default(),
+            config.pool.synthetic_users,
+            &config.pool.roles,
+        ));
+    }
+
+    let (saved, mut pool) = if local.exists() {
+        println!("Using local user pool");
+        let pool: UserPool = serde_json::from_str(&tokio::fs::read_to_string(local).await?)?;
+
+        let saved = UserPool {
+            users: pool.users.clone(),
+        };
+
+        (Saved::Local(saved), pool)
+    } else {
+        let cached = UserPool::cached(
+            &Helix::default(),
+            &Credentials::read().user_id,
+            config.pool.source,
+            &cache::dir()?,
+            config.pool.ttl(),
+        )
+        .await?;
+
+        let pool = cached.edited();
+
+        (Saved::Cached(cached), pool)
+    };
+
+    *SAVED.lock() = Some(saved);
+
+    if let Some(mode) = config.pool.randomise {
+        pool.randomise(&config.pool.roles, mode);
+    }
+
+    Ok(pool)
+}
+
+/// Gives out roles in the pool at random, at the configured ratios
+pub fn randomise(mode: RandomiseMode) {
+    USERS.lock().randomise(&Config::read().pool.roles, mode);
+}
+
+/// Downloads the pool again, keeping the colors of everyone already in it and the edits made to it
+///
+/// The download is merged into the cached pool rather than the one in use, so roles that were randomised don't show
+/// up as changes. The merged pool is then used, with its roles randomised again if the config asks for it.
+pub async fn refresh() -> Result<PoolDiff, PoolError> {
+    let config = Config::read();
+
+    if config.is_offline() {
+        return Err(PoolCacheError::Offline.into());
+    }
+
+    if matches!(*SAVED.lock(), Some(Saved::Local(_))) {
+        return Err(PoolError::LocalPool);
+    }
+
+    let fresh = UserPool::download(
+        &Helix::default(),
+        &Credentials::read().user_id,
+        config.pool.source,
+    )
+    .await
+    .map_err(PoolCacheError::from)?;
+
+    let dir = cache::dir()?;
+
+    let mut users = USERS.lock();
+    let mut saved = SAVED.lock();
+
+    let mut cached = match saved.take() {
+        Some(Saved::Cached(cached)) => cached,
+        _ => CachedPool::load(&dir)?
+            .unwrap_or_else(|| CachedPool::new(UserPool { users: Vec::new() }, config.pool.source)),
+    };
+
+    let diff = cached.update(fresh, config.pool.source);
+    let saving = cached.save(&dir);
+
+    let mut pool = cached.edited();
+    *saved = Some(Saved::Cached(cached));
+    saving?;
+
+    // The real roles came back with the download
+    if let Some(mode) = config.pool.randomise {
+        pool.randomise(&config.pool.roles, mode);
+    }
+
+    *users = pool;
+
+    Ok(diff)
+}
+
+/// Everyone in the pool, or only those matching the query
+pub fn list(query: Option<&str>) -> Vec<TwitchUser> {
+    let pool = USERS.lock();
+
+    match query {
+        Some(query) => pool.search(query).into_iter().cloned().collect(),
+        None => pool.users.clone(),
+    }
+}
+
+pub fn get(uid: &str) -> Result<TwitchUser, PoolError> {
+    USERS
+        .lock()
+        .find(uid)
+        .cloned()
+        .ok_or_else(|| PoolEditError::NotFound(uid.to_string()).into())
+}
+
+pub fn add(user: TwitchUser) -> Result<(), PoolError> {
+    apply(PoolEdit::Add { user }).map(drop)
+}
+
+pub fn edit(uid: &str, patch: UserPatch) -> Result<TwitchUser, PoolError> {
+    apply(PoolEdit::Edit {
+        uid: uid.to_string(),
+        patch,
+    })
+}
+
+pub fn remove(uid: &str) -> Result<TwitchUser, PoolError> {
+    apply(PoolEdit::Remove {
+        uid: uid.to_string(),
+    })
+}
+
+/// What an import added to the pool, and what it left out
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub issues: Vec<String>,
+}
+
+/// Adds everyone in the files to the pool, see [`PoolImport`] for the formats and what is left out
+///
+/// The files are read before the pool is locked, so messages keep being sent while they are.
+pub fn import(paths: &[PathBuf]) -> Result<ImportSummary, PoolError> {
+    let mut files = PoolImport::default();
+
+    for path in paths {
+        files.file(path)?;
+    }
+
+    update(|pool| {
+        let before = pool.users.len();
+
+        let mut import = PoolImport::from_pool(UserPool {
+            users: std::mem::take(&mut pool.users),
+        });
+        import.merge(files);
+
+        let (imported, issues) = import.finish();
+        *pool = imported;
+
+        // Everyone already in the pool is kept as they were, and those imported come after them
+        let edits = pool.users[before..]
+            .iter()
+            .map(|user| PoolEdit::Add { user: user.clone() })
+            .collect();
+
+        let summary = ImportSummary {
+            added: pool.users.len() - before,
+            issues: issues.iter().map(ToString::to_string).collect(),
+        };
+
+        Ok((summary, edits))
+    })
+}
+
+/// Makes the edit to the pool in use, and saves it
+fn apply(edit: PoolEdit) -> Result<TwitchUser, PoolError> {
+    update(|pool| Ok((pool.apply(&edit)?, vec![edit])))
+}
+
+/// Changes the pool in use, then saves the edits that were made to it where the pool came from
+///
+/// The change is only made to the pool in use, where it is checked, and the edits it returns are made to the saved
+/// pool as they are.
+fn update<T>(
+    change: impl FnOnce(&mut UserPool) -> Result<(T, Vec<PoolEdit>), PoolError>,
+) -> Result<T, PoolError> {
+    let mut users = USERS.lock();
+    let mut saved = SAVED.lock();
+
+    let (changed, edits) = change(&mut users)?;
+    drop(users);
+
+    // Only edits wait for the save, messages can be sent in the meantime
+    if let Some(saved) = &mut *saved {
+        saved.record(edits)?;
+    }
+
+    Ok(changed)
+}
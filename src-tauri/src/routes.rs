@@ -1,9 +1,40 @@
 use std::path::PathBuf;
 
-use actix_web::{HttpRequest, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use twitch_api::{
+    cache::PoolCacheError, edit::PoolEditError, edit::UserPatch, randomise::RandomiseMode,
+    TwitchUser,
+};
+
+use crate::{
+    pool::{self, PoolError},
+    scheduler::{Control, JobId},
+};
 
 // TODO: Actual errors not just option returned
 
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(twitch)
+        .service(credentials)
+        .service(queue_status)
+        .service(pause_queue)
+        .service(resume_queue)
+        .service(skip_sleep)
+        .service(cancel_job)
+        .service(clear_queue)
+        .service(set_speed)
+        .service(refresh_pool)
+        .service(randomise_pool)
+        .service(list_users)
+        .service(get_user)
+        .service(add_user)
+        .service(edit_user)
+        .service(delete_user)
+        .service(import_users)
+        .route("/ws/", web::get().to(crate::irc::handle_ws));
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RouteError {
     #[error("Could not find the given path")]
@@ -114,7 +145,169 @@ const credentials = "{api_token}";
         "#
     );
 
+    // The token changes whenever it is refreshed, so this must never be cached
     HttpResponse::Ok()
         .content_type("application/javascript")
+        .insert_header(("Cache-Control", "no-store"))
         .body(file)
 }
+
+#[actix_web::get("/queue")]
+async fn queue_status() -> HttpResponse {
+    match crate::queue_status().await {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::post("/queue/pause")]
+async fn pause_queue() -> HttpResponse {
+    crate::send_control(Control::Pause);
+    HttpResponse::NoContent().finish()
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::post("/queue/resume")]
+async fn resume_queue() -> HttpResponse {
+    crate::send_control(Control::Resume);
+    HttpResponse::NoContent().finish()
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::post("/queue/skip")]
+async fn skip_sleep() -> HttpResponse {
+    crate::send_control(Control::Skip);
+    HttpResponse::NoContent().finish()
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::delete("/queue/{id}")]
+async fn cancel_job(id: web::Path<JobId>) -> HttpResponse {
+    crate::send_control(Control::Cancel(id.into_inner()));
+    HttpResponse::NoContent().finish()
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::delete("/queue")]
+async fn clear_queue() -> HttpResponse {
+    crate::send_control(Control::Clear);
+    HttpResponse::NoContent().finish()
+}
+
+#[actix_web::put("/speed/{speed}")]
+async fn set_speed(speed: web::Path<f64>) -> HttpResponse {
+    match crate::set_speed(speed.into_inner()).await {
+        Some(Ok(())) => HttpResponse::NoContent().finish(),
+        Some(Err(e)) => HttpResponse::BadRequest().body(e.to_string()),
+        None => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// Responds with the status matching what went wrong
+fn pool_error(e: &PoolError) -> HttpResponse {
+    let mut resp = match e {
+        PoolError::Edit(PoolEditError::NotFound(_)) => HttpResponse::NotFound(),
+        PoolError::Edit(
+            PoolEditError::DuplicateUid(_)
+            | PoolEditError::DuplicateName(_)
+            | PoolEditError::LastUser,
+        )
+        | PoolError::LocalPool => HttpResponse::Conflict(),
+        PoolError::Edit(PoolEditError::Empty) | PoolError::Import(_) => HttpResponse::BadRequest(),
+        PoolError::Cache(PoolCacheError::Offline) => HttpResponse::ServiceUnavailable(),
+        PoolError::Cache(PoolCacheError::Download(_)) => HttpResponse::BadGateway(),
+        PoolError::Cache(_) | PoolError::Io(_) | PoolError::Serialize(_) => {
+            HttpResponse::InternalServerError()
+        }
+    };
+
+    resp.body(e.to_string())
+}
+
+#[actix_web::post("/pool/refresh")]
+async fn refresh_pool() -> HttpResponse {
+    match pool::refresh().await {
+        Ok(diff) => HttpResponse::Ok().json(diff),
+        Err(e) => pool_error(&e),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RandomiseQuery {
+    #[serde(default)]
+    mode: RandomiseMode,
+}
+
+/// Randomises the roles in the pool, `?mode=noise` keeps the real ones too
+#[allow(clippy::unused_async)]
+#[actix_web::post("/pool/randomise")]
+async fn randomise_pool(query: web::Query<RandomiseQuery>) -> HttpResponse {
+    pool::randomise(query.mode);
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+}
+
+/// Everyone in the pool, `?q=` only lists those whose name or uid contains it
+#[allow(clippy::unused_async)]
+#[actix_web::get("/pool/users")]
+async fn list_users(query: web::Query<SearchQuery>) -> HttpResponse {
+    HttpResponse::Ok().json(pool::list(query.q.as_deref()))
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::get("/pool/users/{uid}")]
+async fn get_user(uid: web::Path<String>) -> HttpResponse {
+    match pool::get(&uid) {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(e) => pool_error(&e),
+    }
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::post("/pool/users")]
+async fn add_user(user: web::Json<TwitchUser>) -> HttpResponse {
+    let user = user.into_inner();
+
+    match pool::add(user.clone()) {
+        Ok(()) => HttpResponse::Created().json(user),
+        Err(e) => pool_error(&e),
+    }
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::patch("/pool/users/{uid}")]
+async fn edit_user(uid: web::Path<String>, patch: web::Json<UserPatch>) -> HttpResponse {
+    match pool::edit(&uid, patch.into_inner()) {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(e) => pool_error(&e),
+    }
+}
+
+#[allow(clippy::unused_async)]
+#[actix_web::delete("/pool/users/{uid}")]
+async fn delete_user(uid: web::Path<String>) -> HttpResponse {
+    match pool::remove(&uid) {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(e) => pool_error(&e),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ImportRequest {
+    paths: Vec<PathBuf>,
+}
+
+/// Imports the files into the pool, listing whoever was left out and why
+#[allow(clippy::unused_async)]
+#[actix_web::post("/pool/import")]
+async fn import_users(request: web::Json<ImportRequest>) -> HttpResponse {
+    match pool::import(&request.paths) {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(e) => pool_error(&e),
+    }
+}
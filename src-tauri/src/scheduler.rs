@@ -0,0 +1,752 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    time::Duration,
+};
+
+use commands::{amount::Amount, speed::SpeedError, Command};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+use tokio::{sync::oneshot, time::Instant};
+use twitch_api::irc::IrcMessage;
+
+pub type JobId = u64;
+
+/// A request to the task running the scheduler
+#[derive(Debug)]
+pub enum Control {
+    Schedule(Command),
+    /// Queues recorded messages, each with its delay from the first
+    Replay(Vec<(Duration, IrcMessage)>),
+    Pause,
+    Resume,
+    /// Skips the current wait, so the next message is sent immediately
+    Skip,
+    Cancel(JobId),
+    Clear,
+    Status(oneshot::Sender<QueueStatus>),
+    /// Changes the playback speed, including for anything already queued
+    SetSpeed(f64, oneshot::Sender<Result<(), SpeedError>>),
+}
+
+/// A single message waiting to be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Emission {
+    /// A message sent as a user from the pool
+    Send {
+        message: String,
+        username: String,
+        channel: Option<String>,
+    },
+    /// A recorded message, sent again exactly as it was
+    Replay(IrcMessage),
+}
+
+/// Where a job's messages come from
+#[derive(Debug)]
+enum Source {
+    /// The same message sent a number of times
+    Repeat {
+        emission: Emission,
+        /// The number of messages left to send, including this one
+        remaining: usize,
+        delay: Amount<u64>,
+        /// Picks the delays between messages, seeded so they match those the job's end was worked out from
+        rng: Box<StdRng>,
+    },
+    /// Recorded messages, the first of which is sent next
+    Replay(VecDeque<(Duration, IrcMessage)>),
+}
+
+/// The next message a job is due to send, which is armed again with the following delay once it has been sent
+#[derive(Debug)]
+struct Pending {
+    at: Instant,
+    job: JobId,
+    source: Source,
+}
+
+impl Pending {
+    fn emission(&self) -> Emission {
+        match &self.source {
+            Source::Repeat { emission, .. } => emission.clone(),
+            Source::Replay(messages) => {
+                Emission::Replay(messages.front().expect("replays are not empty").1.clone())
+            }
+        }
+    }
+
+    /// The number of messages left to send, including this one
+    fn remaining(&self) -> usize {
+        match &self.source {
+            Source::Repeat { remaining, .. } => *remaining,
+            Source::Replay(messages) => messages.len(),
+        }
+    }
+
+    /// Moves on to the next message, or [`None`] if this was the last
+    fn rearm(mut self) -> Option<Self> {
+        match &mut self.source {
+            Source::Repeat {
+                remaining,
+                delay,
+                rng,
+                ..
+            } => {
+                if *remaining <= 1 {
+                    return None;
+                }
+
+                *remaining -= 1;
+                self.at += delay.get_delay_with(rng.as_mut());
+            }
+            Source::Replay(messages) => {
+                let (sent, _) = messages.pop_front()?;
+                let (next, _) = messages.front()?;
+
+                self.at += commands::speed::scale(next.saturating_sub(sent));
+            }
+        }
+
+        Some(self)
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.job) == (other.at, other.job)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    /// Jobs run in the order they were queued, so messages due at the same instant are sent in that order too
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.job).cmp(&(other.at, other.job))
+    }
+}
+
+/// A queued command, spanning from when it starts until its last delay has passed
+#[derive(Debug)]
+struct Job {
+    id: JobId,
+    command: String,
+    start: Instant,
+    end: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: JobId,
+    pub command: String,
+    /// Milliseconds until the job starts, zero if it is already running
+    pub eta_ms: u128,
+    /// Milliseconds until the job has finished
+    pub finishes_in_ms: u128,
+    /// The number of messages the job has left to send
+    pub remaining: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStatus {
+    pub paused: bool,
+    pub speed: f64,
+    pub jobs: Vec<JobStatus>,
+}
+
+/// The most messages [`Scheduler::pop_due`] returns at once, so a job sending many without a delay can't hog memory
+const MAX_BATCH: usize = 1000;
+
+/// Turns commands into a timeline of emissions, ordered by when they are due
+///
+/// Commands run one after another, as they would in a script, so each is scheduled from where the previous one finished.
+/// Only the next message of each job is queued, so a command sending a message many times takes no more memory than one.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<Pending>>,
+    jobs: Vec<Job>,
+    /// The point at which the last queued command finishes
+    cursor: Option<Instant>,
+    paused_at: Option<Instant>,
+    next_job: JobId,
+}
+
+impl Scheduler {
+    /// Queues the given command to run once everything before it has finished
+    pub fn schedule(&mut self, cmd: &Command, now: Instant) -> JobId {
+        // Anything queued while paused starts from the moment it was paused, and is moved along on resume
+        let now = self.paused_at.unwrap_or(now);
+
+        let start = self.cursor.map_or(now, |cursor| cursor.max(now));
+
+        let (end, source) = match cmd {
+            Command::Send {
+                message,
+                username,
+                count,
+                delay,
+                channel,
+            } => {
+                let rng = StdRng::from_entropy();
+                let end = start + total_delay(*delay, *count, rng.clone());
+
+                let source = Source::Repeat {
+                    emission: Emission::Send {
+                        message: message.clone(),
+                        username: username.clone(),
+                        channel: channel.clone(),
+                    },
+                    remaining: *count,
+                    delay: *delay,
+                    rng: Box::new(rng),
+                };
+
+                (end, (*count > 0).then_some(source))
+            }
+            Command::Sleep { .. } => (start + cmd.get_delay(), None),
+        };
+
+        self.push_job(cmd.to_string(), start, end, source)
+    }
+
+    /// Queues recorded messages to be sent again as they were, once everything before them has finished
+    pub fn replay(&mut self, messages: Vec<(Duration, IrcMessage)>, now: Instant) -> JobId {
+        let now = self.paused_at.unwrap_or(now);
+        let start = self.cursor.map_or(now, |cursor| cursor.max(now));
+
+        let command = format!("replay of {} messages", messages.len());
+
+        // The gaps are scaled one at a time when sending, so the end is worked out the same way
+        let end = start
+            + messages
+                .windows(2)
+                .map(|pair| commands::speed::scale(pair[1].0.saturating_sub(pair[0].0)))
+                .sum::<Duration>();
+
+        let source = (!messages.is_empty()).then(|| Source::Replay(messages.into()));
+
+        self.push_job(command, start, end, source)
+    }
+
+    fn push_job(
+        &mut self,
+        command: String,
+        start: Instant,
+        end: Instant,
+        source: Option<Source>,
+    ) -> JobId {
+        self.next_job += 1;
+        let id = self.next_job;
+
+        if let Some(source) = source {
+            self.queue.push(Reverse(Pending {
+                at: start,
+                job: id,
+                source,
+            }));
+        }
+
+        self.jobs.push(Job {
+            id,
+            command,
+            start,
+            end,
+        });
+
+        self.cursor = Some(end);
+
+        id
+    }
+
+    /// When the next emission is due, if there is one and the queue is not paused
+    #[must_use]
+    pub fn next_due(&self) -> Option<Instant> {
+        if self.paused_at.is_some() {
+            return None;
+        }
+
+        self.queue.peek().map(|Reverse(pending)| pending.at)
+    }
+
+    /// Removes and returns every emission due at or before the given instant, in order
+    pub fn pop_due(&mut self, now: Instant) -> Vec<Emission> {
+        let mut due = Vec::new();
+
+        if self.paused_at.is_some() {
+            return due;
+        }
+
+        while due.len() < MAX_BATCH
+            && self
+                .queue
+                .peek()
+                .is_some_and(|Reverse(pending)| pending.at <= now)
+        {
+            let Reverse(pending) = self.queue.pop().expect("peeked value");
+            due.push(pending.emission());
+
+            if let Some(next) = pending.rearm() {
+                self.queue.push(Reverse(next));
+            }
+        }
+
+        self.jobs.retain(|job| job.end > now);
+
+        due
+    }
+
+    /// The number of messages left to send
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue
+            .iter()
+            .map(|Reverse(pending)| pending.remaining())
+            .sum()
+    }
+
+    /// Moves every pending emission, job and the cursor to a new time
+    fn retime(&mut self, f: impl Fn(Instant) -> Instant) {
+        self.queue = std::mem::take(&mut self.queue)
+            .into_iter()
+            .map(|Reverse(mut pending)| {
+                pending.at = f(pending.at);
+                Reverse(pending)
+            })
+            .collect();
+
+        for job in &mut self.jobs {
+            job.start = f(job.start);
+            job.end = f(job.end);
+        }
+
+        self.cursor = self.cursor.map(f);
+    }
+
+    pub fn pause(&mut self, now: Instant) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    pub fn resume(&mut self, now: Instant) {
+        let Some(paused_at) = self.paused_at.take() else {
+            return;
+        };
+
+        let paused_for = now.saturating_duration_since(paused_at);
+
+        self.retime(|at| if at >= paused_at { at + paused_for } else { at });
+    }
+
+    /// Brings everything forward so that the next emission is due now
+    pub fn skip(&mut self, now: Instant) {
+        let Some(Reverse(next)) = self.queue.peek() else {
+            // Nothing left to send, so only the trailing sleeps remain
+            self.jobs.clear();
+            self.cursor = None;
+            return;
+        };
+
+        let now = self.paused_at.unwrap_or(now);
+        let skipped = next.at.saturating_duration_since(now);
+
+        self.retime(|at| {
+            if at > now {
+                (at - skipped).max(now)
+            } else {
+                at
+            }
+        });
+    }
+
+    /// Removes the given job, moving any that were queued after it forward to fill the gap
+    pub fn cancel(&mut self, id: JobId, now: Instant) -> bool {
+        let Some(index) = self.jobs.iter().position(|job| job.id == id) else {
+            return false;
+        };
+
+        let job = self.jobs.remove(index);
+
+        let now = self.paused_at.unwrap_or(now);
+        let remaining = job.end.saturating_duration_since(job.start.max(now));
+
+        self.queue.retain(|Reverse(pending)| pending.job != id);
+
+        self.retime(|at| {
+            if at >= job.end {
+                (at - remaining).max(now)
+            } else {
+                at
+            }
+        });
+
+        true
+    }
+
+    /// Changes the playback speed, stretching or squashing the remaining wait for everything queued
+    pub fn set_speed(&mut self, speed: f64, now: Instant) -> Result<(), SpeedError> {
+        let previous = commands::speed::set_speed(speed)?;
+        let factor = previous / speed;
+
+        let now = self.paused_at.unwrap_or(now);
+
+        self.retime(|at| {
+            if at > now {
+                now + (at - now).mul_f64(factor)
+            } else {
+                at
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Removes every pending job
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.jobs.clear();
+        self.cursor = None;
+    }
+
+    #[must_use]
+    pub fn status(&self, now: Instant) -> QueueStatus {
+        let now = self.paused_at.unwrap_or(now);
+
+        let jobs = self
+            .jobs
+            .iter()
+            .map(|job| JobStatus {
+                id: job.id,
+                command: job.command.clone(),
+                eta_ms: job.start.saturating_duration_since(now).as_millis(),
+                finishes_in_ms: job.end.saturating_duration_since(now).as_millis(),
+                remaining: self
+                    .queue
+                    .iter()
+                    .find(|Reverse(pending)| pending.job == job.id)
+                    .map_or(0, |Reverse(pending)| pending.remaining()),
+            })
+            .collect();
+
+        QueueStatus {
+            paused: self.paused_at.is_some(),
+            speed: commands::speed::speed(),
+            jobs,
+        }
+    }
+}
+
+/// How long a command sending `count` messages takes, picking each delay as [`Pending::rearm`] will
+fn total_delay(delay: Amount<u64>, count: usize, mut rng: StdRng) -> Duration {
+    match delay {
+        Amount::Single(_) => delay
+            .get_delay()
+            .saturating_mul(u32::try_from(count).unwrap_or(u32::MAX)),
+        Amount::Range { .. } => (0..count).map(|_| delay.get_delay_with(&mut rng)).sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use commands::amount::Amount;
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    /// The playback speed is global, so tests relying on timing must not run alongside one changing it
+    static SPEED: Mutex<()> = Mutex::new(());
+
+    fn send(message: &str, count: usize, delay: u64) -> Command {
+        Command::Send {
+            message: message.to_string(),
+            username: String::from("random"),
+            count,
+            delay: Amount::Single(delay),
+            channel: None,
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn message(emission: &Emission) -> &str {
+        match emission {
+            Emission::Send { message, .. } => message,
+            Emission::Replay(msg) => &msg.params[1],
+        }
+    }
+
+    #[test]
+    fn test_commands_run_in_sequence() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&send("first", 2, 100), start);
+        scheduler.schedule(&Command::Sleep { delay: 50 }, start);
+        scheduler.schedule(&send("second", 1, 0), start);
+
+        assert_eq!(scheduler.len(), 3);
+        assert_eq!(scheduler.next_due(), Some(start));
+
+        let due = scheduler.pop_due(start);
+        assert_eq!(due.len(), 1);
+        assert_eq!(message(&due[0]), "first");
+
+        assert_eq!(scheduler.next_due(), Some(start + ms(100)));
+
+        // The second message waits for both sends of the first command, and the sleep
+        assert!(scheduler
+            .pop_due(start + ms(249))
+            .iter()
+            .all(|emission| message(emission) == "first"));
+
+        let due = scheduler.pop_due(start + ms(250));
+        assert_eq!(message(&due[0]), "second");
+        assert_eq!(scheduler.len(), 0);
+    }
+
+    #[test]
+    fn test_idle_scheduler_starts_from_now() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&send("first", 1, 100), start);
+        scheduler.pop_due(start);
+
+        // The previous command finished long ago, so the next should not be scheduled in the past
+        let later = start + Duration::from_secs(10);
+        scheduler.schedule(&send("second", 1, 0), later);
+
+        assert_eq!(scheduler.next_due(), Some(later));
+    }
+
+    #[test]
+    fn test_many_queued_messages_stay_ordered() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        for i in 0..5000 {
+            scheduler.schedule(&send(&i.to_string(), 1, 1), start);
+        }
+
+        // Handed out in batches, so keep going until everything has been sent
+        let mut due = Vec::new();
+        while scheduler.len() > 0 {
+            due.extend(scheduler.pop_due(start + Duration::from_secs(10)));
+        }
+        let expected: Vec<String> = (0..5000).map(|i: u32| i.to_string()).collect();
+
+        assert_eq!(
+            due.into_iter()
+                .map(|emission| message(&emission).to_string())
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&Command::Sleep { delay: 100 }, start);
+        scheduler.schedule(&send("first", 1, 0), start);
+
+        scheduler.pause(start + ms(50));
+        assert_eq!(scheduler.next_due(), None);
+        assert!(scheduler.pop_due(start + ms(500)).is_empty());
+        assert!(scheduler.status(start + ms(500)).paused);
+
+        // Paused for 450ms, so the remaining 50ms of sleep continues from there
+        scheduler.resume(start + ms(500));
+        assert_eq!(scheduler.next_due(), Some(start + ms(550)));
+    }
+
+    #[test]
+    fn test_skip() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&Command::Sleep { delay: 1000 }, start);
+        scheduler.schedule(&send("first", 2, 100), start);
+
+        scheduler.skip(start + ms(10));
+        assert_eq!(scheduler.next_due(), Some(start + ms(10)));
+
+        scheduler.pop_due(start + ms(10));
+        assert_eq!(scheduler.next_due(), Some(start + ms(110)));
+    }
+
+    #[test]
+    fn test_set_speed() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&send("first", 3, 1000), start);
+        scheduler.pop_due(start);
+
+        // Halfway through the first delay, playback becomes twice as fast
+        scheduler.set_speed(2.0, start + ms(500)).unwrap();
+        assert_eq!(scheduler.next_due(), Some(start + ms(750)));
+
+        scheduler.pop_due(start + ms(750));
+        assert_eq!(scheduler.next_due(), Some(start + ms(1250)));
+
+        // New commands are scheduled at the new speed
+        scheduler.schedule(&send("second", 2, 1000), start + ms(750));
+        assert_eq!(
+            scheduler.status(start + ms(750)).jobs[1].finishes_in_ms,
+            2000
+        );
+
+        assert!(scheduler.set_speed(1000.0, start).is_err());
+        scheduler.set_speed(1.0, start + ms(750)).unwrap();
+        assert_eq!(scheduler.next_due(), Some(start + ms(1750)));
+    }
+
+    #[test]
+    fn test_cancel_and_status() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&send("first", 1, 0), start);
+        let sleep = scheduler.schedule(&Command::Sleep { delay: 1000 }, start);
+        let second = scheduler.schedule(&send("second", 2, 100), start);
+
+        let status = scheduler.status(start);
+        assert_eq!(status.jobs.len(), 3);
+        assert_eq!(status.jobs[2].id, second);
+        assert_eq!(status.jobs[2].eta_ms, 1000);
+        assert_eq!(status.jobs[2].remaining, 2);
+
+        assert!(scheduler.cancel(sleep, start));
+        assert!(!scheduler.cancel(sleep, start));
+
+        // The second job moves forward to fill the cancelled sleep
+        let status = scheduler.status(start);
+        assert_eq!(status.jobs.len(), 2);
+        assert_eq!(status.jobs[1].eta_ms, 0);
+
+        assert!(scheduler.cancel(second, start));
+        assert_eq!(scheduler.len(), 1);
+
+        scheduler.clear();
+        assert_eq!(scheduler.len(), 0);
+        assert!(scheduler.status(start).jobs.is_empty());
+    }
+
+    #[test]
+    fn test_repeated_messages_are_armed_one_at_a_time() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.schedule(&send("many", 10_000_000, 0), start);
+        scheduler.schedule(&send("after", 1, 0), start);
+
+        assert_eq!(scheduler.queue.len(), 2);
+        assert_eq!(scheduler.len(), 10_000_001);
+        assert_eq!(scheduler.status(start).jobs[0].remaining, 10_000_000);
+
+        // Everything is due at once, but is handed out in batches, in order
+        let due = scheduler.pop_due(start);
+        assert_eq!(due.len(), MAX_BATCH);
+        assert!(due.iter().all(|emission| message(emission) == "many"));
+        assert_eq!(scheduler.queue.len(), 2);
+    }
+
+    #[test]
+    fn test_random_delays_end_with_the_job() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        let random = Command::Send {
+            message: String::from("random"),
+            username: String::from("random"),
+            count: 50,
+            delay: Amount::Range {
+                start: 10,
+                finish: 100,
+            },
+            channel: None,
+        };
+        scheduler.schedule(&random, start);
+        scheduler.schedule(&send("after", 1, 0), start);
+
+        let end = start + ms(scheduler.status(start).jobs[1].eta_ms.try_into().unwrap());
+
+        let mut sent = Vec::new();
+        while let Some(due) = scheduler.next_due() {
+            sent.extend(
+                scheduler
+                    .pop_due(due)
+                    .iter()
+                    .map(|emission| (due, message(emission).to_string())),
+            );
+        }
+
+        assert_eq!(sent.len(), 51);
+        assert!(sent[..50]
+            .iter()
+            .all(|(at, message)| *at < end && message == "random"));
+        assert!(sent[50].0.duration_since(end) < ms(1));
+    }
+
+    #[test]
+    fn test_replay_is_queued_like_a_command() {
+        let _speed = SPEED.lock();
+        let start = Instant::now();
+        let mut scheduler = Scheduler::default();
+
+        let recorded = |text: &str| IrcMessage::new("PRIVMSG").param("#channel").param(text);
+
+        scheduler.schedule(&Command::Sleep { delay: 100 }, start);
+        let replay = scheduler.replay(
+            vec![
+                (ms(0), recorded("first")),
+                (ms(50), recorded("second")),
+                (ms(200), recorded("third")),
+            ],
+            start,
+        );
+        scheduler.schedule(&send("after", 1, 0), start);
+
+        let status = scheduler.status(start);
+        assert_eq!(status.jobs[1].id, replay);
+        assert_eq!(status.jobs[1].remaining, 3);
+        assert_eq!(status.jobs[2].eta_ms, 300);
+
+        assert!(scheduler.pop_due(start + ms(99)).is_empty());
+        assert_eq!(
+            scheduler.pop_due(start + ms(100)),
+            [Emission::Replay(recorded("first"))]
+        );
+
+        // Pausing holds the replay back too
+        scheduler.pause(start + ms(120));
+        scheduler.resume(start + ms(220));
+        assert_eq!(scheduler.next_due(), Some(start + ms(250)));
+
+        assert!(scheduler.cancel(replay, start + ms(220)));
+        assert_eq!(scheduler.next_due(), Some(start + ms(220)));
+        assert_eq!(message(&scheduler.pop_due(start + ms(220))[0]), "after");
+    }
+}
@@ -0,0 +1,578 @@
+//! Management of the sessions recorded into the cache dir
+//!
+//! A session is every file sharing the same timestamped stem, i.e. `2023-11-04-18-30-12.cmdir` along with its
+//! `.jsonl` recording, converted `.commands` script and `.toml` metadata.
+//! Other files in the cache dir are left alone, unless they come with session metadata.
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use time::{format_description::FormatItem, macros::format_description, PrimitiveDateTime};
+
+/// The extensions of the files making up a session
+const EXTENSIONS: [&str; 4] = ["cmdir", "jsonl", "commands", "toml"];
+
+/// Sessions are named after when they started
+const ID_FORMAT: &[FormatItem] =
+    format_description!("[year]-[month]-[day]-[hour]-[minute]-[second]");
+
+/// The id of the session being recorded by this instance, which is never removed
+static CURRENT: OnceCell<String> = OnceCell::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("Failed to interact with system IO: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No session named {0}")]
+    NotFound(String),
+    #[error("Session {0} is still being recorded")]
+    Current(String),
+    #[error("Failed to read session metadata: {0}")]
+    InvalidMeta(#[from] toml::de::Error),
+    #[error("Failed to write session metadata: {0}")]
+    SaveMeta(#[from] toml::ser::Error),
+}
+
+/// How many sessions are kept, where unset limits keep everything
+///
+/// Named sessions are always kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    /// Keep only this many of the latest sessions
+    pub keep_last: Option<usize>,
+    /// Keep only sessions changed within this many days
+    pub keep_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionMeta {
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl SessionMeta {
+    /// Adds the tags the session does not have yet
+    pub fn tag<T: AsRef<str>>(&mut self, tags: &[T]) {
+        for tag in tags {
+            if !self.tags.iter().any(|existing| existing == tag.as_ref()) {
+                self.tags.push(tag.as_ref().to_string());
+            }
+        }
+    }
+
+    pub fn untag<T: AsRef<str>>(&mut self, tags: &[T]) {
+        self.tags
+            .retain(|existing| !tags.iter().any(|tag| tag.as_ref() == existing));
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    /// The number of messages sent during the session
+    pub messages: usize,
+    /// Unix time in seconds the session was last written to
+    pub modified: u64,
+    pub files: Vec<PathBuf>,
+}
+
+impl Session {
+    fn file(&self, extension: &str) -> Option<&Path> {
+        self.files
+            .iter()
+            .find(|file| file.extension().is_some_and(|ext| ext == extension))
+            .map(PathBuf::as_path)
+    }
+
+    /// The lossless recording of the session, if there is one
+    #[must_use]
+    pub fn recording(&self) -> Option<&Path> {
+        self.file("jsonl")
+    }
+
+    fn is_current(&self) -> bool {
+        CURRENT.get() == Some(&self.id)
+    }
+}
+
+// #[cfg(not(debug_assertions))]
+pub fn dir() -> PathBuf {
+    directories::ProjectDirs::from("com", "jewelexx", "FauxChat")
+        .unwrap()
+        .cache_dir()
+        .to_path_buf()
+}
+
+// #[cfg(debug_assertions)]
+// pub fn dir() -> PathBuf {
+//     PathBuf::new()
+// }
+
+/// Path for the recording of this session, named after when it started
+pub fn new_path() -> PathBuf {
+    let folder = dir();
+
+    fs::create_dir_all(&folder).expect("created cmdir directory");
+
+    let now: time::OffsetDateTime = SystemTime::now().into();
+
+    let id = now.format(ID_FORMAT).unwrap();
+
+    // Save as .cmdir file (short for command intermediate representation)
+    // This file is converted on shutdown, to have the "end_pause" converted into regular sleep commands
+    let path = folder.join(format!("{id}.cmdir"));
+
+    CURRENT.set(id).expect("only one session per launch");
+
+    path
+}
+
+/// Converts the session being recorded into a script, or removes it if nothing was sent
+///
+/// Returns the path of the script, if one was written.
+pub fn finish(cmdir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    if count_messages(&[cmdir.to_path_buf()]) == 0 {
+        for extension in EXTENSIONS {
+            let file = cmdir.with_extension(extension);
+
+            if file.exists() {
+                fs::remove_file(file)?;
+            }
+        }
+
+        return Ok(None);
+    }
+
+    // The cmdir is kept, so that the session can be converted again if needed
+    crate::cli::convert_cmdir(cmdir).map(Some)
+}
+
+/// Counts the messages sent, from whichever file the session has
+fn count_messages(files: &[PathBuf]) -> usize {
+    let count = |extension: &str, counts: fn(&str) -> bool| {
+        let file = files
+            .iter()
+            .find(|file| file.extension().is_some_and(|ext| ext == extension))?;
+
+        let contents = fs::read_to_string(file).ok()?;
+
+        Some(contents.lines().filter(|line| counts(line.trim())).count())
+    };
+
+    count("cmdir", |line| line.starts_with("end_pause("))
+        .or_else(|| count("jsonl", |line| !line.is_empty()))
+        .or_else(|| count("commands", |line| line.starts_with("send(")))
+        .unwrap_or_default()
+}
+
+fn read_meta(files: &[PathBuf]) -> Result<SessionMeta, SessionError> {
+    match files
+        .iter()
+        .find(|file| file.extension().is_some_and(|ext| ext == "toml"))
+    {
+        Some(file) => Ok(toml::from_str(&fs::read_to_string(file)?)?),
+        None => Ok(SessionMeta::default()),
+    }
+}
+
+/// Whether the files are a session, either named the way [`new_path`] names them, or a recording with metadata
+fn is_session(id: &str, files: &[PathBuf]) -> bool {
+    let has = |extension: &str| {
+        files
+            .iter()
+            .any(|file| file.extension().is_some_and(|ext| ext == extension))
+    };
+
+    PrimitiveDateTime::parse(id, ID_FORMAT).is_ok()
+        || (has("toml") && (has("cmdir") || has("jsonl") || has("commands")))
+}
+
+/// Lists every session in the directory, latest first
+///
+/// Sessions with metadata that can't be read are skipped with a warning, so they are never pruned.
+pub fn list(dir: &Path) -> Result<Vec<Session>, SessionError> {
+    let mut grouped: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+            continue;
+        };
+
+        if !EXTENSIONS.iter().any(|known| extension == *known) {
+            continue;
+        }
+
+        grouped
+            .entry(stem.to_string_lossy().to_string())
+            .or_default()
+            .push(path);
+    }
+
+    let mut sessions: Vec<_> = grouped
+        .into_iter()
+        .filter(|(id, files)| is_session(id, files))
+        .filter_map(|(id, mut files)| {
+            files.sort();
+
+            let meta = match read_meta(&files) {
+                Ok(meta) => meta,
+                Err(e) => {
+                    warn!("Skipping session {id}: {e}");
+                    return None;
+                }
+            };
+
+            let modified = files
+                .iter()
+                .filter_map(|file| file.metadata().ok()?.modified().ok())
+                .max()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs());
+
+            Some(Session {
+                messages: count_messages(&files),
+                id,
+                name: meta.name,
+                tags: meta.tags,
+                modified,
+                files,
+            })
+        })
+        .collect();
+
+    // Recorded sessions are named after when they started, anything else falls back to its files
+    sessions.sort_by_key(|session| {
+        let started = PrimitiveDateTime::parse(&session.id, ID_FORMAT)
+            .ok()
+            .and_then(|started| u64::try_from(started.assume_utc().unix_timestamp()).ok());
+
+        Reverse((started.unwrap_or(session.modified), session.id.clone()))
+    });
+
+    Ok(sessions)
+}
+
+pub fn find(dir: &Path, id: &str) -> Result<Session, SessionError> {
+    list(dir)?
+        .into_iter()
+        .find(|session| session.id == id)
+        .ok_or_else(|| SessionError::NotFound(id.to_string()))
+}
+
+/// Deletes every file of the session
+pub fn delete(dir: &Path, id: &str) -> Result<(), SessionError> {
+    let session = find(dir, id)?;
+
+    if session.is_current() {
+        return Err(SessionError::Current(session.id));
+    }
+
+    for file in &session.files {
+        fs::remove_file(file)?;
+    }
+
+    Ok(())
+}
+
+/// Changes the metadata of a session, creating it if needed
+pub fn update_meta(
+    dir: &Path,
+    id: &str,
+    update: impl FnOnce(&mut SessionMeta),
+) -> Result<SessionMeta, SessionError> {
+    let session = find(dir, id)?;
+
+    let mut meta = read_meta(&session.files)?;
+    update(&mut meta);
+
+    fs::write(dir.join(format!("{id}.toml")), toml::to_string(&meta)?)?;
+
+    Ok(meta)
+}
+
+/// Removes old sessions as configured, returning the ids removed
+pub fn prune_configured() -> Result<Vec<String>, SessionError> {
+    prune(
+        &dir(),
+        &crate::config::Config::read().sessions,
+        SystemTime::now(),
+    )
+}
+
+/// Removes empty sessions, and any unnamed ones past what should be kept, returning the ids removed
+pub fn prune(
+    dir: &Path,
+    retention: &Retention,
+    now: SystemTime,
+) -> Result<Vec<String>, SessionError> {
+    let cutoff = retention
+        .keep_days
+        .and_then(|days| now.checked_sub(Duration::from_secs(days * 24 * 60 * 60)))
+        .and_then(|cutoff| cutoff.duration_since(UNIX_EPOCH).ok())
+        .map(|cutoff| cutoff.as_secs());
+
+    let mut kept = 0;
+    let mut removed = Vec::new();
+
+    for session in list(dir)? {
+        if session.is_current() || session.name.is_some() {
+            continue;
+        }
+
+        let expired = cutoff.is_some_and(|cutoff| session.modified < cutoff);
+        let over_limit = retention.keep_last.is_some_and(|keep| kept >= keep);
+
+        if session.messages == 0 || expired || over_limit {
+            delete(dir, &session.id)?;
+            removed.push(session.id);
+        } else {
+            kept += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+
+    fn session_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fauxchat-{name}-{}", std::process::id()));
+
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn record(dir: &Path, id: &str, messages: usize) {
+        let mut cmdir = String::new();
+
+        for i in 0..messages {
+            writeln!(cmdir, "end_pause({i})\nsend(\"Hey!\", 1, 0, \"someone\")").unwrap();
+        }
+
+        fs::write(dir.join(format!("{id}.cmdir")), cmdir).unwrap();
+        fs::write(dir.join(format!("{id}.jsonl")), "").unwrap();
+    }
+
+    #[test]
+    fn test_list() {
+        let dir = session_dir("list");
+
+        record(&dir, "2023-11-04-18-30-12", 2);
+        record(&dir, "2023-11-05-18-30-12", 0);
+        fs::write(dir.join("unrelated.txt"), "").unwrap();
+
+        let sessions = list(&dir).unwrap();
+
+        assert_eq!(
+            sessions.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+            vec!["2023-11-05-18-30-12", "2023-11-04-18-30-12"]
+        );
+        assert_eq!(sessions[1].messages, 2);
+        assert_eq!(sessions[1].files.len(), 2);
+        assert!(sessions[1].recording().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_name_and_tag() {
+        let dir = session_dir("meta");
+
+        record(&dir, "2023-11-04-18-30-12", 1);
+
+        update_meta(&dir, "2023-11-04-18-30-12", |meta| {
+            meta.name = Some(String::from("Raid test"));
+            meta.tag(&["raid", "demo"]);
+        })
+        .unwrap();
+
+        // Tagging again adds to the tags, without repeating any
+        update_meta(&dir, "2023-11-04-18-30-12", |meta| {
+            meta.tag(&["raid", "long"]);
+            meta.untag(&["demo"]);
+        })
+        .unwrap();
+
+        let session = find(&dir, "2023-11-04-18-30-12").unwrap();
+
+        assert_eq!(session.name.as_deref(), Some("Raid test"));
+        assert_eq!(session.tags, vec!["raid", "long"]);
+        assert_eq!(session.files.len(), 3);
+
+        assert!(matches!(
+            update_meta(&dir, "missing", |_| ()),
+            Err(SessionError::NotFound(_))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_other_files_are_left_alone() {
+        let dir = session_dir("other");
+
+        record(&dir, "2023-11-04-18-30-12", 0);
+        record(&dir, "2023-11-05-18-30-12", 1);
+        fs::write(dir.join("2023-11-05-18-30-12.toml"), "name = [").unwrap();
+        fs::write(dir.join("settings.toml"), "").unwrap();
+        fs::write(dir.join("export.jsonl"), "").unwrap();
+        record(&dir, "imported", 1);
+        fs::write(dir.join("imported.toml"), "name = \"Imported\"").unwrap();
+
+        let sessions = list(&dir).unwrap();
+
+        // The session with broken metadata is skipped rather than failing the whole list
+        assert_eq!(
+            sessions.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+            vec!["imported", "2023-11-04-18-30-12"]
+        );
+
+        let retention = Retention {
+            keep_last: Some(0),
+            keep_days: None,
+        };
+        assert_eq!(
+            prune(&dir, &retention, SystemTime::now()).unwrap(),
+            vec!["2023-11-04-18-30-12"]
+        );
+
+        for kept in [
+            "2023-11-05-18-30-12.cmdir",
+            "settings.toml",
+            "export.jsonl",
+            "imported.jsonl",
+        ] {
+            assert!(dir.join(kept).exists(), "{kept} was removed");
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_imported_sort_by_age() {
+        let dir = session_dir("imported");
+
+        record(&dir, "2023-11-04-18-30-12", 1);
+        record(&dir, "2023-11-05-18-30-12", 1);
+        fs::write(dir.join("0-imported.toml"), "").unwrap();
+        fs::write(dir.join("0-imported.jsonl"), "{}\n").unwrap();
+
+        // Imported just now, so it is newer than both recordings despite sorting first by name
+        let sessions = list(&dir).unwrap();
+        assert_eq!(
+            sessions.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+            vec!["0-imported", "2023-11-05-18-30-12", "2023-11-04-18-30-12"]
+        );
+
+        let retention = Retention {
+            keep_last: Some(2),
+            keep_days: None,
+        };
+        assert_eq!(
+            prune(&dir, &retention, SystemTime::now()).unwrap(),
+            vec!["2023-11-04-18-30-12"]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_finish_empty() {
+        let dir = session_dir("finish");
+
+        record(&dir, "2023-11-04-18-30-12", 0);
+        record(&dir, "2023-11-05-18-30-12", 1);
+
+        assert_eq!(
+            finish(&dir.join("2023-11-04-18-30-12.cmdir")).unwrap(),
+            None
+        );
+        assert_eq!(
+            finish(&dir.join("2023-11-05-18-30-12.cmdir")).unwrap(),
+            Some(dir.join("2023-11-05-18-30-12.commands"))
+        );
+
+        let sessions = list(&dir).unwrap();
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].files.len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = session_dir("prune");
+
+        record(&dir, "2023-11-01-00-00-00", 1);
+        record(&dir, "2023-11-02-00-00-00", 1);
+        record(&dir, "2023-11-03-00-00-00", 0);
+        record(&dir, "2023-11-04-00-00-00", 1);
+        record(&dir, "2023-11-05-00-00-00", 1);
+
+        update_meta(&dir, "2023-11-01-00-00-00", |meta| {
+            meta.name = Some(String::from("Keep me"));
+        })
+        .unwrap();
+
+        let retention = Retention {
+            keep_last: Some(2),
+            keep_days: None,
+        };
+
+        let removed = prune(&dir, &retention, SystemTime::now()).unwrap();
+
+        assert_eq!(removed, vec!["2023-11-03-00-00-00", "2023-11-02-00-00-00"]);
+        assert_eq!(
+            list(&dir)
+                .unwrap()
+                .iter()
+                .map(|s| s.id.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "2023-11-05-00-00-00",
+                "2023-11-04-00-00-00",
+                "2023-11-01-00-00-00"
+            ]
+        );
+
+        // Everything was just written, so pretend two days have passed
+        let retention = Retention {
+            keep_last: None,
+            keep_days: Some(1),
+        };
+
+        let later = SystemTime::now() + Duration::from_secs(2 * 24 * 60 * 60);
+        let removed = prune(&dir, &retention, later).unwrap();
+
+        assert_eq!(removed, vec!["2023-11-05-00-00-00", "2023-11-04-00-00-00"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
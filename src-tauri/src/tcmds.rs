@@ -1,12 +1,26 @@
 use std::{
     fs::File,
     io::{self, BufRead},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use commands::{Command, CommandsError};
+use commands::{speed::SpeedError, Command, CommandsError};
+use twitch_api::{
+    cache::PoolDiff,
+    edit::UserPatch,
+    export::{self, ExportError, ExportFormat, SubtitleOptions},
+    randomise::RandomiseMode,
+    recording::{self, RecordingError},
+    TwitchUser,
+};
 
-use crate::ready_message;
+use crate::{
+    pool::{self, ImportSummary, PoolError},
+    ready_message,
+    scheduler::{Control, JobId, QueueStatus},
+    send_control,
+    sessions::{self, Session, SessionError},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CommandError {
@@ -15,6 +29,24 @@ pub enum CommandError {
 
     #[error("Failed to parse command: {0}")]
     ParseError(#[from] CommandsError),
+
+    #[error("Failed to set playback speed: {0}")]
+    Speed(#[from] SpeedError),
+
+    #[error("The scheduler has stopped")]
+    SchedulerStopped,
+
+    #[error("Failed to read recording: {0}")]
+    Recording(#[from] RecordingError),
+
+    #[error("Failed to export recording: {0}")]
+    Export(#[from] ExportError),
+
+    #[error("Failed to manage sessions: {0}")]
+    Session(#[from] SessionError),
+
+    #[error("Failed to change the pool: {0}")]
+    Pool(#[from] PoolError),
 }
 
 impl serde::Serialize for CommandError {
@@ -46,7 +78,9 @@ pub fn load_file(path: &str) -> Result<()> {
     let lines = read_lines(path)?;
 
     for line in lines {
-        let Ok(parsed) = Command::try_from(line?) else { continue };
+        let Ok(parsed) = Command::try_from(line?) else {
+            continue;
+        };
 
         ready_message(parsed);
     }
@@ -55,7 +89,13 @@ pub fn load_file(path: &str) -> Result<()> {
 }
 
 #[tauri::command]
-pub fn send_message(message: &str, username: &str, count: usize, delay: u64) {
+pub fn send_message(
+    message: &str,
+    username: &str,
+    count: usize,
+    delay: u64,
+    channel: Option<String>,
+) {
     info!("Sending message");
 
     let command = Command::Send {
@@ -63,11 +103,188 @@ pub fn send_message(message: &str, username: &str, count: usize, delay: u64) {
         username: username.to_string(),
         count,
         delay: commands::amount::Amount::Single(delay),
+        channel,
     };
 
     ready_message(command);
 }
 
+/// Replays a `.jsonl` session recording exactly as it was sent, once everything queued before it has been sent
+#[tauri::command]
+pub async fn replay_recording(path: String) -> Result<()> {
+    info!("Replaying {path}");
+
+    let events = recording::read(io::BufReader::new(File::open(&path)?))?;
+    let messages = recording::replay(events)?;
+
+    send_control(Control::Replay(messages));
+
+    Ok(())
+}
+
+/// Exports a `.jsonl` session recording next to it, returning the path of the export
+#[tauri::command]
+pub fn export_recording(path: &str, format: &str) -> Result<String> {
+    let format: ExportFormat = format.parse()?;
+
+    let events = recording::read(io::BufReader::new(File::open(path)?))?;
+    let exported = export::export(&events, format, &SubtitleOptions::default())?;
+
+    let output = Path::new(path).with_extension(format.extension());
+    std::fs::write(&output, exported)?;
+
+    Ok(output.display().to_string())
+}
+
+#[tauri::command]
+pub fn list_sessions() -> Result<Vec<Session>> {
+    Ok(sessions::list(&sessions::dir())?)
+}
+
+/// Plays a recorded session again, exactly when it has a recording, or from its script otherwise
+#[tauri::command]
+pub async fn open_session(id: String) -> Result<()> {
+    let session = sessions::find(&sessions::dir(), &id)?;
+
+    if let Some(recording) = session.recording() {
+        return replay_recording(recording.display().to_string()).await;
+    }
+
+    let script = session
+        .files
+        .iter()
+        .find(|file| file.extension().is_some_and(|ext| ext == "commands"));
+
+    match script {
+        Some(script) => load_file(&script.display().to_string()),
+        None => Err(SessionError::NotFound(id).into()),
+    }
+}
+
+#[tauri::command]
+pub fn delete_session(id: &str) -> Result<()> {
+    Ok(sessions::delete(&sessions::dir(), id)?)
+}
+
+/// Names a session, which also keeps it from being removed, or clears the name if none is given
+#[tauri::command]
+pub fn name_session(id: &str, name: Option<String>) -> Result<()> {
+    sessions::update_meta(&sessions::dir(), id, |meta| meta.name = name)?;
+
+    Ok(())
+}
+
+/// Adds the tags to a session, keeping those it already has
+#[allow(clippy::needless_pass_by_value)]
+#[tauri::command]
+pub fn tag_session(id: &str, tags: Vec<String>) -> Result<()> {
+    sessions::update_meta(&sessions::dir(), id, |meta| meta.tag(&tags))?;
+
+    Ok(())
+}
+
+/// Removes the tags from a session
+#[allow(clippy::needless_pass_by_value)]
+#[tauri::command]
+pub fn untag_session(id: &str, tags: Vec<String>) -> Result<()> {
+    sessions::update_meta(&sessions::dir(), id, |meta| meta.untag(&tags))?;
+
+    Ok(())
+}
+
+/// Removes old sessions as configured, returning the ids removed
+#[tauri::command]
+pub fn prune_sessions() -> Result<Vec<String>> {
+    Ok(sessions::prune_configured()?)
+}
+
+#[tauri::command]
+pub fn pause_queue() {
+    send_control(Control::Pause);
+}
+
+#[tauri::command]
+pub fn resume_queue() {
+    send_control(Control::Resume);
+}
+
+#[tauri::command]
+pub fn skip_sleep() {
+    send_control(Control::Skip);
+}
+
+#[tauri::command]
+pub fn cancel_job(id: JobId) {
+    send_control(Control::Cancel(id));
+}
+
+#[tauri::command]
+pub fn clear_queue() {
+    send_control(Control::Clear);
+}
+
+#[tauri::command]
+pub async fn queue_status() -> Option<QueueStatus> {
+    crate::queue_status().await
+}
+
+#[tauri::command]
+pub async fn set_speed(speed: f64) -> Result<()> {
+    crate::set_speed(speed)
+        .await
+        .ok_or(CommandError::SchedulerStopped)??;
+
+    Ok(())
+}
+
+/// Downloads the pool again, returning what changed
+#[tauri::command]
+pub async fn refresh_pool() -> Result<PoolDiff> {
+    Ok(pool::refresh().await?)
+}
+
+/// Gives out roles in the pool at random, replacing the real ones unless the mode is `noise`
+#[tauri::command]
+pub fn randomise_pool(mode: Option<RandomiseMode>) {
+    pool::randomise(mode.unwrap_or_default());
+}
+
+/// Everyone in the pool, or only those whose name or uid contains the query
+#[allow(clippy::needless_pass_by_value)]
+#[tauri::command]
+pub fn list_users(query: Option<String>) -> Vec<TwitchUser> {
+    pool::list(query.as_deref())
+}
+
+#[tauri::command]
+pub fn get_user(uid: &str) -> Result<TwitchUser> {
+    Ok(pool::get(uid)?)
+}
+
+/// Adds the user to the pool, and saves it
+#[tauri::command]
+pub fn add_user(user: TwitchUser) -> Result<()> {
+    Ok(pool::add(user)?)
+}
+
+/// Changes whatever the patch sets, and saves the pool, returning the user as they are now
+#[tauri::command]
+pub fn edit_user(uid: &str, patch: UserPatch) -> Result<TwitchUser> {
+    Ok(pool::edit(uid, patch)?)
+}
+
+#[tauri::command]
+pub fn delete_user(uid: &str) -> Result<TwitchUser> {
+    Ok(pool::remove(uid)?)
+}
+
+/// Adds everyone in the `.json` pools, `.csv` files and lists of usernames to the pool, and saves it
+#[allow(clippy::needless_pass_by_value)]
+#[tauri::command]
+pub fn import_users(paths: Vec<PathBuf>) -> Result<ImportSummary> {
+    Ok(pool::import(&paths)?)
+}
+
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where
     P: AsRef<Path>,